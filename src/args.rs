@@ -1,5 +1,11 @@
 pub use clap::Parser;
 use std::fs;
+use std::time::SystemTime;
+
+use fsevents_parser_rs::filter::FlagExpr;
+use fsevents_parser_rs::gaps;
+use fsevents_parser_rs::sort;
+use fsevents_parser_rs::timeline::{self, Anchor};
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about=None)]
@@ -18,6 +24,119 @@ pub struct ArgParse {
     #[clap(short, long, value_enum,
         default_value_t = ArgsOutputFormat::Json)]
     pub format: ArgsOutputFormat,
+
+    /// Resynchronize after corrupt records and pages instead of skipping them
+    #[clap(long, action)]
+    pub recover: bool,
+
+    /// Carve pages and gzip archives out of the input file (disk image,
+    /// unallocated space or memory dump) instead of reading a directory
+    #[clap(long, action)]
+    pub carve: bool,
+
+    /// Estimate a time window for every event from the archive mtimes
+    #[clap(long, action)]
+    pub estimate_times: bool,
+
+    /// Known time of an event, as EVENT_ID=TIME with TIME in unix seconds or
+    /// UTC YYYY-MM-DD HH:MM:SS; implies --estimate-times
+    #[clap(long, value_parser = parse_anchor, value_name = "EVENT_ID=TIME")]
+    pub anchor: Vec<Anchor>,
+
+    /// Bodyfile or CSV (path, inode, crtime, mtime) from the same image whose
+    /// matching events anchor event times; implies --estimate-times
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub timestamps: Option<String>,
+
+    /// Write all records sorted by event id across archives
+    #[clap(long, action)]
+    pub sort: bool,
+
+    /// Records kept in memory by --sort before a sorted run is spilled to a
+    /// temporary file
+    #[clap(long, value_parser, value_name = "RECORDS", default_value_t = sort::DEFAULT_RUN_SIZE)]
+    pub sort_run_size: usize,
+
+    /// Also export the lifecycle of every file: when it was first seen,
+    /// created, modified, renamed and deleted
+    #[clap(long, action)]
+    pub history: bool,
+
+    /// Also write the tree of every path seen, each marked existed, deleted,
+    /// transient or renamed, as JSON when FILE ends in .json, as text otherwise
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub tree: Option<String>,
+
+    /// Only export entries whose path matches PATTERN, a regex or, prefixed
+    /// with glob:, a shell-style glob; may be repeated
+    #[clap(long, value_parser, value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Do not export entries whose path matches PATTERN, a regex or, prefixed
+    /// with glob:, a shell-style glob; may be repeated
+    #[clap(long, value_parser, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Only export entries whose flags satisfy EXPR, flag names combined with
+    /// &, |, ! and parentheses, e.g. "DELETE & IS_FILE & !RENAME"
+    #[clap(long, value_parser = parse_flags, value_name = "EXPR")]
+    pub flags: Option<FlagExpr>,
+
+    /// Only export entries with an event id of at least ID, decimal or 0x hex
+    #[clap(long, value_parser = parse_event_id, value_name = "ID")]
+    pub min_event_id: Option<u64>,
+
+    /// Only export entries with an event id of at most ID, decimal or 0x hex
+    #[clap(long, value_parser = parse_event_id, value_name = "ID")]
+    pub max_event_id: Option<u64>,
+
+    /// Only export events that may have happened at or after TIME, in unix
+    /// seconds or UTC YYYY-MM-DD HH:MM:SS, judged from archive mtimes and
+    /// estimated event times
+    #[clap(long, value_parser = parse_time, value_name = "TIME")]
+    pub since: Option<SystemTime>,
+
+    /// Only export events that may have happened at or before TIME, in unix
+    /// seconds or UTC YYYY-MM-DD HH:MM:SS, judged from archive mtimes and
+    /// estimated event times
+    #[clap(long, value_parser = parse_time, value_name = "TIME")]
+    pub until: Option<SystemTime>,
+
+    /// Smallest number of event ids missing between two archives reported as
+    /// a gap; events of other volumes leave small gaps
+    #[clap(long, value_parser, value_name = "IDS", default_value_t = gaps::DEFAULT_MIN_GAP)]
+    pub min_gap: u64,
+}
+
+fn parse_anchor(s: &str) -> Result<Anchor, String> {
+    let (event_id, time) = s
+        .split_once('=')
+        .ok_or_else(|| String::from("expected EVENT_ID=TIME"))?;
+    let event_id = parse_event_id(event_id)?;
+    let time = parse_time(time)?;
+
+    Ok(Anchor {
+        event_id,
+        time,
+        exact: true,
+        source: String::from("user"),
+    })
+}
+
+fn parse_event_id(s: &str) -> Result<u64, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("invalid event id: {}", e))
+}
+
+fn parse_time(s: &str) -> Result<SystemTime, String> {
+    timeline::parse_time(s).ok_or_else(|| format!("invalid time: {}", s))
+}
+
+fn parse_flags(s: &str) -> Result<FlagExpr, String> {
+    FlagExpr::parse(s)
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -25,15 +144,29 @@ pub enum ArgsOutputFormat {
     Json,
     Csv,
     Sqlite,
+    /// Tab separated, in the column layout of the Python FSEventsParser
+    Fseventsparser,
 }
 
 pub fn validate_args(args: &ArgParse) -> bool {
     // check input file existence
-    if let Err(err) = fs::read_dir(&args.input_path) {
+    if args.carve {
+        if let Err(err) = fs::File::open(&args.input_path) {
+            println!("invalid input path: {}", err);
+            return false;
+        }
+    } else if let Err(err) = fs::read_dir(&args.input_path) {
         println!("invalid input path: {}", err);
         return false;
     }
 
+    if let Some(timestamps) = &args.timestamps {
+        if let Err(err) = fs::metadata(timestamps) {
+            println!("invalid timestamp listing: {}", err);
+            return false;
+        }
+    }
+
     // check output path dir existence
     if let Err(err) = fs::remove_file(&args.output_path) {
         if err.kind() != std::io::ErrorKind::NotFound {
@@ -42,5 +175,5 @@ pub fn validate_args(args: &ArgParse) -> bool {
         }
     }
 
-    return true;
+    true
 }
@@ -0,0 +1,273 @@
+//! Carving of fseventsd data out of raw images, unallocated space dumps and
+//! memory dumps.
+//!
+//! The image is scanned for gzip member headers and bare `1SLD`/`2SLD`/`3SLD`
+//! page magic. Each candidate whose data decodes to fseventsd pages is handed
+//! out as a pseudo archive named `<image>@<offset>`.
+
+use std::cell::Cell;
+use std::fs;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use flate2::bufread::GzDecoder;
+
+use crate::fsevents::{find_page_magic, plausible_record, ArchiveInfo, EntryIterator, PageHeader};
+
+const SCAN_CHUNK: usize = 1 << 20;
+// decompressed bytes searched for a page magic before a gzip hit is dropped
+const GZIP_PROBE: u64 = 1 << 16;
+// largest bare page accepted
+const MAX_PAGE_SIZE: u32 = 1 << 24;
+
+/// Entries of a carved pseudo archive.
+pub type CarvedEntries<'a> = EntryIterator<Box<dyn Read + 'a>>;
+
+/// A candidate found while scanning an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hit {
+    /// Offset of a gzip member header.
+    Gzip(u64),
+    /// Offset of an uncompressed page magic.
+    Page(u64),
+}
+
+impl Hit {
+    pub fn offset(&self) -> u64 {
+        match self {
+            Hit::Gzip(o) | Hit::Page(o) => *o,
+        }
+    }
+}
+
+/// Scans the file at `path` for gzip headers and page magic, in file order.
+pub fn scan(path: &str) -> Result<Vec<Hit>, std::io::Error> {
+    let mut fd = fs::File::open(path)?;
+    let mut hits = vec![];
+    let mut buf = vec![0u8; SCAN_CHUNK];
+    let mut kept = 0; // bytes carried over from the previous chunk
+    let mut base: u64 = 0; // file offset of buf[0]
+
+    loop {
+        let n = fd.read(&mut buf[kept..])?;
+        if n == 0 {
+            break;
+        }
+        let len = kept + n;
+
+        for i in 0..len.saturating_sub(3) {
+            let window = &buf[i..i + 4];
+            // gzip magic, deflate, no reserved flag bits
+            if window[0] == 0x1f && window[1] == 0x8b && window[2] == 0x08 && window[3] & 0xe0 == 0
+            {
+                hits.push(Hit::Gzip(base + i as u64));
+            } else if find_page_magic(window) == Some(0) {
+                hits.push(Hit::Page(base + i as u64));
+            }
+        }
+
+        // keep the last 3 bytes, a magic may start there
+        kept = len.min(3);
+        buf.copy_within(len - kept..len, 0);
+        base += (len - kept) as u64;
+    }
+
+    Ok(hits)
+}
+
+/// Carves the image at `path`, calling `export` with every pseudo archive
+/// found. Returns the number of pseudo archives.
+///
+/// Carved archives have no file timestamps, they are set to the epoch. Data
+/// consumed by one pseudo archive is not carved again.
+pub fn carve<F>(path: &str, recover: bool, mut export: F) -> Result<usize, std::io::Error>
+where
+    F: FnMut(&ArchiveInfo, &mut CarvedEntries),
+{
+    let image = match std::path::Path::new(path).file_name() {
+        Some(s) => String::from(s.to_str().unwrap_or("")),
+        None => String::from(path),
+    };
+    let hits = scan(path)?;
+    let mut fd = fs::File::open(path)?;
+
+    let mut carved = 0;
+    let mut skip_until: u64 = 0;
+    for hit in hits {
+        let offset = hit.offset();
+        if offset < skip_until {
+            continue;
+        }
+        fd.seek(SeekFrom::Start(offset))?;
+
+        let info = ArchiveInfo {
+            filename: format!("{}@{:#x}", image, offset),
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            volume_uuid: None,
+            source_offset: Some(offset),
+            last_event_id_hint: None,
+        };
+        let consumed = match hit {
+            Hit::Gzip(_) => carve_gzip(&fd, &info, recover, &mut export),
+            Hit::Page(_) => carve_page(&fd, &info, recover, &mut export)?,
+        };
+        if let Some(consumed) = consumed {
+            carved += 1;
+            skip_until = offset + consumed;
+        }
+    }
+
+    Ok(carved)
+}
+
+// decode one gzip member, returning the compressed size if it held pages
+fn carve_gzip<F>(fd: &fs::File, info: &ArchiveInfo, recover: bool, export: &mut F) -> Option<u64>
+where
+    F: FnMut(&ArchiveInfo, &mut CarvedEntries),
+{
+    let consumed = Rc::new(Cell::new(0));
+    let reader = CountingReader {
+        inner: BufReader::new(fd),
+        consumed: consumed.clone(),
+    };
+    let mut decoder = GzDecoder::new(reader);
+
+    // only members that decompress to pages are fseventsd data
+    let mut head = vec![];
+    _ = (&mut decoder).take(GZIP_PROBE).read_to_end(&mut head);
+    find_page_magic(&head)?;
+
+    let stream: Box<dyn Read> = Box::new(Cursor::new(head).chain(decoder));
+    let mut entries = EntryIterator::new(stream)
+        .with_archive_name(&info.filename)
+        .with_recovery(recover)
+        .with_gzip();
+    export(info, &mut entries);
+    drop(entries);
+
+    Some(consumed.get())
+}
+
+// parse one bare page, returning its size if it holds a plausible record
+fn carve_page<F>(
+    fd: &fs::File,
+    info: &ArchiveInfo,
+    recover: bool,
+    export: &mut F,
+) -> Result<Option<u64>, std::io::Error>
+where
+    F: FnMut(&ArchiveInfo, &mut CarvedEntries),
+{
+    let mut page = vec![];
+    fd.take(PageHeader::len() as u64).read_to_end(&mut page)?;
+    let header = match PageHeader::parse(&page) {
+        Ok(h) if h.stream_size as usize > PageHeader::len() && h.stream_size <= MAX_PAGE_SIZE => h,
+        _ => return Ok(None),
+    };
+    fd.take((header.stream_size as usize - PageHeader::len()) as u64)
+        .read_to_end(&mut page)?;
+    if plausible_record(&page[PageHeader::len()..], header.version).is_none() {
+        return Ok(None);
+    }
+
+    let size = page.len() as u64;
+    let stream: Box<dyn Read> = Box::new(Cursor::new(page));
+    let mut entries = EntryIterator::new(stream)
+        .with_archive_name(&info.filename)
+        .with_recovery(recover);
+    export(info, &mut entries);
+
+    Ok(Some(size))
+}
+
+// counts the bytes the decoder takes from the image
+struct CountingReader<R: BufRead> {
+    inner: R,
+    consumed: Rc<Cell<u64>>,
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.consumed.set(self.consumed.get() + n as u64);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed.set(self.consumed.get() + amt as u64);
+        self.inner.consume(amt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_v1(path: &[u8], event_id: u64, flags: u32) -> Vec<u8> {
+        let mut record = path.to_vec();
+        record.push(0);
+        record.extend_from_slice(&event_id.to_le_bytes());
+        record.extend_from_slice(&flags.to_le_bytes());
+        record
+    }
+
+    fn page_v1(body: &[u8]) -> Vec<u8> {
+        let mut page = b"1SLD".to_vec();
+        page.extend_from_slice(&0x12345678u32.to_le_bytes());
+        page.extend_from_slice(&((body.len() + PageHeader::len()) as u32).to_le_bytes());
+        page.extend_from_slice(body);
+        page
+    }
+
+    // carve an image holding `data` between zeroes, returning the number of
+    // archives and the paths of the entries carved
+    fn carve_bytes(name: &str, data: &[u8]) -> (usize, Vec<String>) {
+        let path = std::env::temp_dir().join(format!(
+            "fsevents_parser_rs-carve-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let mut image = vec![0u8; 100];
+        image.extend_from_slice(data);
+        image.extend_from_slice(&[0u8; 50]);
+        fs::write(&path, image).unwrap();
+
+        let mut paths = vec![];
+        let count = carve(path.to_str().unwrap(), false, |_, entries| {
+            paths.extend(entries.filter_map(|r| r.ok()).map(|e| e.full_path));
+        })
+        .unwrap();
+        _ = fs::remove_file(&path);
+        (count, paths)
+    }
+
+    #[test]
+    fn non_utf8_path_at_page_end() {
+        // a record too short for its header right after a non-utf-8 path
+        let mut body = vec![0xff, 0x00];
+        body.extend_from_slice(&[0x01; 12]);
+        let (count, paths) = carve_bytes("short", &page_v1(&body));
+        assert_eq!(count, 0);
+        assert!(paths.is_empty());
+
+        // a page whose first record is fine and whose last one is not utf-8
+        let mut body = record_v1(b"Users/a/ok.txt", 5, 0x00800001);
+        body.extend_from_slice(&record_v1(&[0xff; 20], 6, 0x00800001));
+        let (count, paths) = carve_bytes("last", &page_v1(&body));
+        assert_eq!(count, 1);
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0], "Users/a/ok.txt");
+
+        // a page starting with a non-utf-8 path is not taken for fseventsd data
+        let (count, _) = carve_bytes("first", &page_v1(&record_v1(&[0xff; 20], 6, 1)));
+        assert_eq!(count, 0);
+    }
+} // mod tests
@@ -0,0 +1,502 @@
+//! Predicates deciding which entries are exported.
+//!
+//! Path patterns are regexes searched in `Entry.full_path`, or shell-style
+//! globs when prefixed with `glob:`. Globs match the whole path, relative to
+//! the volume root: `*` and `?` stop at `/`, `**` does not.
+//!
+//! Flag expressions combine flag names with `&`, `|`, `!` and parentheses,
+//! e.g. `DELETE & IS_FILE & !RENAME`; `!` binds tightest, then `&`, then `|`.
+//!
+//! Event id and time bounds are inclusive. An entry is checked against the
+//! time bounds through its estimated time window, and passes when that window
+//! overlaps them or was not estimated.
+
+use std::time::SystemTime;
+
+use regex::Regex;
+
+use crate::fsevents::{name_id_ranges, ArchiveInfo, Entry, EventFlag};
+use crate::timeline::TimeWindow;
+
+/// Every condition an entry must meet to be exported.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    pub paths: PathFilter,
+    pub flags: Option<FlagExpr>,
+    pub min_event_id: Option<u64>,
+    pub max_event_id: Option<u64>,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+impl EntryFilter {
+    pub fn new() -> Self {
+        EntryFilter::default()
+    }
+
+    pub fn with_paths(mut self, paths: PathFilter) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: FlagExpr) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
+    pub fn with_event_ids(mut self, min: Option<u64>, max: Option<u64>) -> Self {
+        self.min_event_id = min;
+        self.max_event_id = max;
+        self
+    }
+
+    pub fn with_times(mut self, since: Option<SystemTime>, until: Option<SystemTime>) -> Self {
+        self.since = since;
+        self.until = until;
+        self
+    }
+
+    /// No entry is left out.
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+            && self.flags.is_none()
+            && self.min_event_id.is_none()
+            && self.max_event_id.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+    }
+
+    pub fn accepts(&self, entry: &Entry) -> bool {
+        self.may_contain(
+            entry.event_id,
+            entry.event_id,
+            TimeWindow {
+                lower: entry.time_lower_bound,
+                upper: entry.time_upper_bound,
+            },
+        ) && self.paths.accepts(entry)
+            && self.flags.as_ref().is_none_or(|f| f.accepts(entry))
+    }
+
+    /// Some event with an id from `first` to `last` that happened in
+    /// `window` may pass; an archive for which this is false can be skipped
+    /// without being read.
+    pub fn may_contain(&self, first: u64, last: u64, window: TimeWindow) -> bool {
+        self.min_event_id.is_none_or(|min| min <= last)
+            && self.max_event_id.is_none_or(|max| first <= max)
+            && self
+                .since
+                .is_none_or(|since| window.upper.is_none_or(|upper| since <= upper))
+            && self
+                .until
+                .is_none_or(|until| window.lower.is_none_or(|lower| lower <= until))
+    }
+
+    /// Tells, for each of `archives` sorted by name, that no entry in it can
+    /// pass, judged from the event ids in the archive names and from the
+    /// mtimes: the events of an archive happened after the archive written
+    /// before it and at or before its own mtime.
+    pub fn skippable(&self, archives: &[ArchiveInfo]) -> Vec<bool> {
+        let mut mtimes = archives.iter().map(|a| a.mtime).collect::<Vec<_>>();
+        mtimes.sort();
+
+        archives
+            .iter()
+            .zip(name_id_ranges(archives))
+            .map(|(info, range)| {
+                let (first, last) = match range {
+                    Some(range) => range,
+                    None => return false,
+                };
+                let window = TimeWindow {
+                    lower: mtimes[..mtimes.partition_point(|t| *t < info.mtime)]
+                        .last()
+                        .copied(),
+                    upper: Some(info.mtime),
+                };
+                !self.may_contain(first, last, window)
+            })
+            .collect()
+    }
+} // impl EntryFilter
+
+/// Include and exclude path patterns. An entry passes when it matches some
+/// include pattern, or there is none, and no exclude pattern.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl PathFilter {
+    pub fn new() -> Self {
+        PathFilter::default()
+    }
+
+    /// Adds a pattern paths must match.
+    pub fn include(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.include.push(compile(pattern)?);
+        Ok(self)
+    }
+
+    /// Adds a pattern paths must not match.
+    pub fn exclude(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.exclude.push(compile(pattern)?);
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(path)))
+            && !self.exclude.iter().any(|re| re.is_match(path))
+    }
+
+    pub fn accepts(&self, entry: &Entry) -> bool {
+        self.matches(&entry.full_path)
+    }
+} // impl PathFilter
+
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    match pattern.strip_prefix("glob:") {
+        Some(glob) => Regex::new(&glob_to_regex(glob)),
+        None => Regex::new(pattern),
+    }
+}
+
+/// Translates a shell-style glob to an anchored regex. `[!...]` negates a
+/// class; a leading `/` is ignored, fsevents paths have none.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^/?");
+    let mut chars = glob.trim_start_matches('/').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // "**/" also matches no directory at all
+                match chars.peek() == Some(&'/') {
+                    true => {
+                        chars.next();
+                        re.push_str("(?:.*/)?");
+                    }
+                    false => re.push_str(".*"),
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                re.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    re.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    re.push('$');
+    re
+}
+
+/// Boolean expression over the flags of an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlagExpr {
+    Flag(EventFlag),
+    Not(Box<FlagExpr>),
+    And(Box<FlagExpr>, Box<FlagExpr>),
+    Or(Box<FlagExpr>, Box<FlagExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl FlagExpr {
+    /// Parses an expression such as `CREATE_FILE|CREATE_DIR`; flags are
+    /// named as in [`EventFlag::from_name`], `&&` and `||` are accepted too.
+    pub fn parse(s: &str) -> Result<FlagExpr, String> {
+        let tokens = tokenize(s)?;
+        let mut pos = 0;
+        let expr = parse_or(&tokens, &mut pos)?;
+        match tokens.get(pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {} in flag expression", describe(token))),
+        }
+    }
+
+    pub fn matches(&self, flags: EventFlag) -> bool {
+        match self {
+            FlagExpr::Flag(flag) => flags.contains(*flag),
+            FlagExpr::Not(e) => !e.matches(flags),
+            FlagExpr::And(a, b) => a.matches(flags) && b.matches(flags),
+            FlagExpr::Or(a, b) => a.matches(flags) || b.matches(flags),
+        }
+    }
+
+    pub fn accepts(&self, entry: &Entry) -> bool {
+        self.matches(entry.flags)
+    }
+} // impl FlagExpr
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '&' | '|' => {
+                // "&&" and "||" read as "&" and "|"
+                chars.next_if_eq(&c);
+                tokens.push(match c {
+                    '&' => Token::And,
+                    _ => Token::Or,
+                });
+            }
+            '!' => tokens.push(Token::Not),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut name = String::from(c);
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("unexpected '{}' in flag expression", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Name(name) => format!("'{}'", name),
+        Token::And => String::from("'&'"),
+        Token::Or => String::from("'|'"),
+        Token::Not => String::from("'!'"),
+        Token::Open => String::from("'('"),
+        Token::Close => String::from("')'"),
+    }
+}
+
+// or := and ('|' and)*
+fn parse_or(tokens: &[Token], pos: &mut usize) -> Result<FlagExpr, String> {
+    let mut expr = parse_and(tokens, pos)?;
+    while tokens.get(*pos) == Some(&Token::Or) {
+        *pos += 1;
+        expr = FlagExpr::Or(Box::new(expr), Box::new(parse_and(tokens, pos)?));
+    }
+    Ok(expr)
+}
+
+// and := not ('&' not)*
+fn parse_and(tokens: &[Token], pos: &mut usize) -> Result<FlagExpr, String> {
+    let mut expr = parse_not(tokens, pos)?;
+    while tokens.get(*pos) == Some(&Token::And) {
+        *pos += 1;
+        expr = FlagExpr::And(Box::new(expr), Box::new(parse_not(tokens, pos)?));
+    }
+    Ok(expr)
+}
+
+// not := '!' not | name | '(' or ')'
+fn parse_not(tokens: &[Token], pos: &mut usize) -> Result<FlagExpr, String> {
+    let token = tokens
+        .get(*pos)
+        .ok_or_else(|| String::from("unexpected end of flag expression"))?;
+    *pos += 1;
+
+    match token {
+        Token::Not => Ok(FlagExpr::Not(Box::new(parse_not(tokens, pos)?))),
+        Token::Name(name) => EventFlag::from_name(name)
+            .map(FlagExpr::Flag)
+            .ok_or_else(|| format!("unknown flag '{}'", name)),
+        Token::Open => {
+            let expr = parse_or(tokens, pos)?;
+            match tokens.get(*pos) {
+                Some(Token::Close) => {
+                    *pos += 1;
+                    Ok(expr)
+                }
+                _ => Err(String::from("missing ')' in flag expression")),
+            }
+        }
+        token => Err(format!("unexpected {} in flag expression", describe(token))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn archive(last: u64, mtime: u64) -> ArchiveInfo {
+        let mtime = UNIX_EPOCH + Duration::from_secs(mtime);
+        ArchiveInfo {
+            filename: format!("{:016x}", last),
+            mtime,
+            ctime: mtime,
+            volume_uuid: None,
+            source_offset: None,
+            last_event_id_hint: Some(last),
+        }
+    }
+
+    #[test]
+    fn skippable_archives_after_a_reset() {
+        // sorted by name, 0x40 was written after a reset
+        let archives = [
+            archive(0x30, 500),
+            archive(0x40, 3000),
+            archive(0x800, 1000),
+        ];
+        let at = |secs| Some(UNIX_EPOCH + Duration::from_secs(secs));
+
+        // the events of 0x800 happened at or before 1000
+        let until = EntryFilter::new().with_times(None, at(2000));
+        assert_eq!(until.skippable(&archives), vec![false, false, false]);
+        let since = EntryFilter::new().with_times(at(2000), None);
+        assert_eq!(since.skippable(&archives), vec![true, false, true]);
+        let before = EntryFilter::new().with_times(None, at(400));
+        assert_eq!(before.skippable(&archives), vec![false, true, true]);
+
+        // ids of 0x40 start over at 0, those of 0x800 after 0x30
+        let ids = EntryFilter::new().with_event_ids(None, Some(0x20));
+        assert_eq!(ids.skippable(&archives), vec![false, false, true]);
+        let ids = EntryFilter::new().with_event_ids(Some(0x35), None);
+        assert_eq!(ids.skippable(&archives), vec![true, false, false]);
+    }
+
+    fn flag(name: &str) -> Box<FlagExpr> {
+        Box::new(FlagExpr::Flag(EventFlag::from_name(name).unwrap()))
+    }
+
+    #[test]
+    fn flag_precedence() {
+        // A|B&!C reads as A|(B&(!C))
+        let expected = FlagExpr::Or(
+            flag("CREATE_FILE"),
+            Box::new(FlagExpr::And(
+                flag("DELETE"),
+                Box::new(FlagExpr::Not(flag("RENAME"))),
+            )),
+        );
+        assert_eq!(
+            FlagExpr::parse("CREATE_FILE|DELETE&!RENAME").unwrap(),
+            expected
+        );
+        assert_eq!(
+            FlagExpr::parse("CREATE_FILE || (DELETE && !RENAME)").unwrap(),
+            expected
+        );
+
+        let expr = FlagExpr::parse("(CREATE_FILE|DELETE)&!RENAME").unwrap();
+        let created_renamed = EventFlag::FSE_CREATE_FILE | EventFlag::FSE_RENAME;
+        assert!(!expr.matches(created_renamed));
+        assert!(expected.matches(created_renamed));
+        assert!(!expected.matches(EventFlag::FSE_DELETE | EventFlag::FSE_RENAME));
+        assert!(!FlagExpr::parse("!!DELETE")
+            .unwrap()
+            .matches(EventFlag::FSE_RENAME));
+    }
+
+    #[test]
+    fn flag_syntax_errors() {
+        for bad in [
+            "(DELETE|RENAME",
+            "DELETE)",
+            "((DELETE)",
+            "()",
+            "DELETE&",
+            "|DELETE",
+        ] {
+            assert!(FlagExpr::parse(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(
+            FlagExpr::parse("DELETE & REMOVED"),
+            Err(String::from("unknown flag 'REMOVED'"))
+        );
+        assert!(FlagExpr::parse("DELETE ^ RENAME").is_err());
+        assert!(FlagExpr::parse("").is_err());
+    }
+
+    fn glob(pattern: &str) -> PathFilter {
+        PathFilter::new()
+            .include(&format!("glob:{}", pattern))
+            .unwrap()
+    }
+
+    #[test]
+    fn glob_double_star() {
+        let any_log = glob("**/*.log");
+        assert!(any_log.matches("a.log"));
+        assert!(any_log.matches("private/var/log/system.log"));
+        assert!(!any_log.matches("private/var/log/system.log.gz"));
+
+        let below = glob("private/var/db/**");
+        assert!(below.matches("private/var/db/uuidtext/00/x"));
+        assert!(!below.matches("private/var/dbx/y"));
+
+        // "**/" may match no directory, "*" does not cross one
+        let middle = glob("Users/**/Desktop/*");
+        assert!(middle.matches("Users/Desktop/a.txt"));
+        assert!(middle.matches("Users/a/b/Desktop/a.txt"));
+        assert!(!middle.matches("Users/a/Desktop/b/a.txt"));
+        assert!(glob("Users/?/x").matches("Users/a/x"));
+        assert!(!glob("Users/?/x").matches("Users/a/b/x"));
+    }
+
+    #[test]
+    fn glob_classes_and_leading_slash() {
+        let class = glob("Users/[!.]*");
+        assert!(class.matches("Users/a"));
+        assert!(!class.matches("Users/.Trash"));
+        assert!(glob("tmp/[ab]").matches("tmp/b"));
+        assert!(!glob("tmp/[ab]").matches("tmp/c"));
+
+        // regex characters are literal
+        assert!(glob("tmp/a+b(1).txt").matches("tmp/a+b(1).txt"));
+        assert!(!glob("tmp/a.txt").matches("tmp/abtxt"));
+
+        // fsevents paths have no leading "/", either form of the glob matches
+        assert!(glob("/Users/*").matches("Users/a"));
+        assert!(glob("Users/*").matches("/Users/a"));
+        assert!(!glob("Users/*").matches("x/Users/a"));
+        assert_eq!(glob_to_regex("/a/b"), glob_to_regex("a/b"));
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let filter = PathFilter::new()
+            .include("^Users/")
+            .unwrap()
+            .exclude("glob:**/Library/Caches/**")
+            .unwrap();
+        assert!(filter.matches("Users/a/x.txt"));
+        assert!(!filter.matches("Users/a/Library/Caches/c/x"));
+        assert!(!filter.matches("private/tmp/x"));
+        assert!(PathFilter::new().include("(").is_err());
+    }
+} // mod tests
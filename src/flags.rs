@@ -0,0 +1,133 @@
+//! Version-aware decoding of record flags into event types.
+//!
+//! The disk log stores the kernel `FSE_*` flag values, not the user-space
+//! `kFSEventStreamEventFlag*` values delivered to FSEventStream clients. The
+//! meaning of a bit also depends on the page version: `1SLD` logs were written
+//! by releases before High Sierra and APFS, which had not yet assigned the
+//! clone, truncated path and remote directory bits. Those bits are left
+//! undecoded in `1SLD` records instead of being mislabeled.
+
+use crate::fsevents::{EventFlag, Version};
+
+/// A flag bit with the event type it stands for.
+#[derive(Debug, Clone, Copy)]
+pub struct FlagDefinition {
+    pub flag: EventFlag,
+    /// Normalized event type, as named by FSEventsParser.
+    pub event_type: &'static str,
+    /// Item type the bit tells, rather than what happened to the item.
+    pub item_type: Option<&'static str>,
+}
+
+const fn def(
+    flag: EventFlag,
+    event_type: &'static str,
+    item_type: Option<&'static str>,
+) -> FlagDefinition {
+    FlagDefinition {
+        flag,
+        event_type,
+        item_type,
+    }
+}
+
+// 1SLD, before High Sierra
+const V1_FLAGS: [FlagDefinition; 22] = [
+    def(EventFlag::FSE_CREATE_FILE, "Created", None),
+    def(EventFlag::FSE_DELETE, "Removed", None),
+    def(EventFlag::FSE_STAT_CHANGED, "InodeMetaMod", None),
+    def(EventFlag::FSE_RENAME, "Renamed", None),
+    def(EventFlag::FSE_CONTENT_MODIFIED, "Modified", None),
+    def(EventFlag::FSE_EXCHANGE, "Exchange", None),
+    def(EventFlag::FSE_FINDER_INFO_CHANGED, "FinderInfoMod", None),
+    def(EventFlag::FSE_CREATE_DIR, "FolderCreated", None),
+    def(EventFlag::FSE_CHOWN, "PermissionChange", None),
+    def(EventFlag::FSE_XATTR_MODIFIED, "ExtendedAttrModified", None),
+    def(EventFlag::FSE_XATTR_REMOVED, "ExtendedAttrRemoved", None),
+    def(EventFlag::FSE_DOCID_CREATED, "DocumentRevisioning", None),
+    def(EventFlag::FSE_DOCID_CHANGED, "DocumentRevisioning", None),
+    def(EventFlag::FSE_UNMOUNT_PENDING, "UnmountPending", None),
+    def(EventFlag::FSE_MODE_LAST_HLINK, "LastHardLinkRemoved", None),
+    def(EventFlag::FSE_MODE_HLINK, "HardLink", Some("HardLink")),
+    def(EventFlag::FSE_IS_SYMLINK, "SymbolicLink", Some("Symlink")),
+    def(EventFlag::FSE_IS_FILE, "FileEvent", Some("File")),
+    def(EventFlag::FSE_IS_DIR, "FolderEvent", Some("Folder")),
+    def(EventFlag::FSE_MOUNT, "Mount", None),
+    def(EventFlag::FSE_UNMOUNT, "Unmount", None),
+    def(EventFlag::FSE_END_TRANSACTION, "EndOfTransaction", None),
+];
+
+// 2SLD, High Sierra and later
+const V2_FLAGS: [FlagDefinition; 26] = [
+    def(EventFlag::FSE_CREATE_FILE, "Created", None),
+    def(EventFlag::FSE_DELETE, "Removed", None),
+    def(EventFlag::FSE_STAT_CHANGED, "InodeMetaMod", None),
+    def(EventFlag::FSE_RENAME, "Renamed", None),
+    def(EventFlag::FSE_CONTENT_MODIFIED, "Modified", None),
+    def(EventFlag::FSE_EXCHANGE, "Exchange", None),
+    def(EventFlag::FSE_FINDER_INFO_CHANGED, "FinderInfoMod", None),
+    def(EventFlag::FSE_CREATE_DIR, "FolderCreated", None),
+    def(EventFlag::FSE_CHOWN, "PermissionChange", None),
+    def(EventFlag::FSE_XATTR_MODIFIED, "ExtendedAttrModified", None),
+    def(EventFlag::FSE_XATTR_REMOVED, "ExtendedAttrRemoved", None),
+    def(EventFlag::FSE_DOCID_CREATED, "DocumentRevisioning", None),
+    def(EventFlag::FSE_DOCID_CHANGED, "DocumentRevisioning", None),
+    def(EventFlag::FSE_UNMOUNT_PENDING, "UnmountPending", None),
+    def(EventFlag::FSE_CLONE, "ItemCloned", None),
+    def(EventFlag::FSE_MODE_CLONE, "ItemCloned", None),
+    def(EventFlag::FSE_TRUNCATED_PATH, "TruncatedPath", None),
+    def(EventFlag::FSE_REMOTE_DIR_EVENT, "RemoteDirEvent", None),
+    def(EventFlag::FSE_MODE_LAST_HLINK, "LastHardLinkRemoved", None),
+    def(EventFlag::FSE_MODE_HLINK, "HardLink", Some("HardLink")),
+    def(EventFlag::FSE_IS_SYMLINK, "SymbolicLink", Some("Symlink")),
+    def(EventFlag::FSE_IS_FILE, "FileEvent", Some("File")),
+    def(EventFlag::FSE_IS_DIR, "FolderEvent", Some("Folder")),
+    def(EventFlag::FSE_MOUNT, "Mount", None),
+    def(EventFlag::FSE_UNMOUNT, "Unmount", None),
+    def(EventFlag::FSE_END_TRANSACTION, "EndOfTransaction", None),
+];
+
+// 3SLD only adds a record field, the flag layout is the 2SLD one
+const V3_FLAGS: [FlagDefinition; 26] = V2_FLAGS;
+
+/// The flag bits defined for records of `version`. Pages of unknown version
+/// are decoded with the current layout.
+pub fn flag_table(version: Version) -> &'static [FlagDefinition] {
+    match version {
+        Version::V1 => &V1_FLAGS,
+        Version::V2 => &V2_FLAGS,
+        Version::V3 | Version::Unknown => &V3_FLAGS,
+    }
+}
+
+/// Mask of the flag bits defined for records of `version`.
+pub fn defined_flags(version: Version) -> u32 {
+    flag_table(version)
+        .iter()
+        .fold(0, |mask, def| mask | def.flag.bits())
+}
+
+/// Normalized event types of the raw `flags` of a record of `version`, in
+/// bit order and without duplicates.
+pub fn event_types(flags: u32, version: Version) -> Vec<&'static str> {
+    let mut types: Vec<&'static str> = vec![];
+    for def in flag_table(version) {
+        if flags & def.flag.bits() != 0 && !types.contains(&def.event_type) {
+            types.push(def.event_type);
+        }
+    }
+    types
+}
+
+/// Item type of the raw `flags` of a record of `version`: File, Folder,
+/// Symlink or HardLink, as FSEventsParser reports it.
+pub fn item_type(flags: u32, version: Version) -> Option<&'static str> {
+    // a folder flag wins over the others, then file, symlink, hard link
+    ["Folder", "File", "Symlink", "HardLink"]
+        .into_iter()
+        .find(|t| {
+            flag_table(version)
+                .iter()
+                .any(|def| def.item_type == Some(*t) && flags & def.flag.bits() != 0)
+        })
+}
@@ -1,15 +1,21 @@
 use regex::Regex;
+use std::collections::HashMap;
 use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fmt, fs};
 
 use bitflags::bitflags;
 use flate2::read::MultiGzDecoder;
 
+use crate::flags;
+
+/// Lists the archive files (16 hex digit names) found in an fseventsd
+/// directory, in event id order.
 pub fn find_archives(dir: &str) -> Vec<String> {
     let fname_re = Regex::new("^[0-9a-f]{16}$").unwrap();
 
     if let Ok(dir_result) = fs::read_dir(dir) {
-        return dir_result
+        let mut archives = dir_result
             .into_iter()
             .filter_map(|s| {
                 // file name & type
@@ -20,17 +26,81 @@ pub fn find_archives(dir: &str) -> Vec<String> {
                 }
             })
             .collect::<Vec<String>>();
+        archives.sort_by_key(|path| {
+            std::path::Path::new(path)
+                .file_name()
+                .and_then(|name| parse_archive_name(name.to_str()?))
+        });
+        return archives;
     }
 
     vec![] // failed to read dir
 }
 
+/// Event id an archive file name stands for: fseventsd names archives after
+/// the hex of the highest event id they hold.
+pub fn parse_archive_name(name: &str) -> Option<u64> {
+    match name.len() == 16 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        true => u64::from_str_radix(name, 16).ok(),
+        false => None,
+    }
+}
+
+/// Event id range of each archive, from the id after the name of the
+/// previous archive written since the same fseventsd reset to its own name,
+/// for archives sorted by name; `None` for archives not named after an event
+/// id. Names going back in mtime order tell where ids started over.
+pub fn name_id_ranges(archives: &[ArchiveInfo]) -> Vec<Option<(u64, u64)>> {
+    // epoch of each archive, counting the resets written before it
+    let mut written = (0..archives.len())
+        .filter_map(|i| Some((archives[i].mtime, archives[i].last_event_id_hint?, i)))
+        .collect::<Vec<(SystemTime, u64, usize)>>();
+    written.sort();
+    let mut epochs = vec![0; archives.len()];
+    let mut epoch = 0;
+    for (k, (_, last, i)) in written.iter().enumerate() {
+        if k > 0 && *last < written[k - 1].1 {
+            epoch += 1;
+        }
+        epochs[*i] = epoch;
+    }
+
+    let mut previous: HashMap<usize, u64> = HashMap::new();
+    archives
+        .iter()
+        .zip(epochs)
+        .map(|(info, epoch)| {
+            let last = info.last_event_id_hint?;
+            let first = previous.get(&epoch).map_or(0, |p| p.saturating_add(1));
+            previous.insert(epoch, last);
+            Some((first, last))
+        })
+        .collect()
+}
+
+/// Reads the volume UUID from the `fseventsd-uuid` file of an fseventsd
+/// directory.
+pub fn read_volume_uuid(dir: &str) -> Option<String> {
+    let content = fs::read(std::path::Path::new(dir).join("fseventsd-uuid")).ok()?;
+    let uuid = String::from_utf8_lossy(&content)
+        .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+        .to_string();
+    match uuid.is_empty() {
+        true => None,
+        false => Some(uuid),
+    }
+}
+
+/// Parses an archive file, logging and discarding failures and empty archives.
 pub fn parse_archive(file_path: &str) -> Option<Archive> {
     // parse from compressed file
     let parse_result = Archive::new(file_path);
     match parse_result {
         Ok(archive) => {
-            if archive.pages.len() == 0 {
+            for e in &archive.errors {
+                println!("failed to parse: {}", e);
+            }
+            if archive.pages.is_empty() {
                 println!("archive contains no pages");
                 return None;
             }
@@ -40,25 +110,34 @@ pub fn parse_archive(file_path: &str) -> Option<Archive> {
             Some(archive)
         }
         Err(e) => {
-            println!("failed to parse: {:?}", e);
+            println!("failed to parse: {}", e);
             None
         }
     }
 }
 
-#[derive(Debug)]
-pub struct Archive {
-    pub pages: Vec<Page>,
-
+/// File name and timestamps of an archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveInfo {
     pub filename: String,
-    pub mtime: std::time::SystemTime,
-    pub ctime: std::time::SystemTime,
+    pub mtime: SystemTime,
+    pub ctime: SystemTime,
+    /// UUID of the volume the archive was logged on, from `fseventsd-uuid`.
+    pub volume_uuid: Option<String>,
+    /// Offset in the source image of data found by carving.
+    pub source_offset: Option<u64>,
+    /// Highest event id of the archive according to its file name.
+    pub last_event_id_hint: Option<u64>,
 }
 
-impl Archive {
-    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        // timestamp & filename
+impl ArchiveInfo {
+    /// Reads the file name and timestamps of the archive at `path`, and the
+    /// volume UUID of the directory it is in.
+    pub fn from_path(path: &str) -> Result<Self, std::io::Error> {
         let metadata = fs::metadata(path)?;
+        let volume_uuid = std::path::Path::new(path)
+            .parent()
+            .and_then(|dir| read_volume_uuid(dir.to_str()?));
         let filename = match std::path::Path::new(path).file_name() {
             Some(s) => match s.to_str() {
                 Some(s) => String::from(s),
@@ -67,32 +146,128 @@ impl Archive {
             None => String::from(""),
         };
 
+        Ok(ArchiveInfo {
+            last_event_id_hint: parse_archive_name(&filename),
+            filename,
+            mtime: metadata.modified()?,
+            ctime: metadata.created()?,
+            volume_uuid,
+            source_offset: None,
+        })
+    }
+
+    /// Reports an archive whose highest event id is not the one in its file
+    /// name, a sign of tampering or corruption.
+    pub fn id_mismatch(&self, max_event_id: u64) -> Option<Diagnostic> {
+        let hint = self.last_event_id_hint?;
+        if hint == max_event_id {
+            return None;
+        }
+
+        Some(Diagnostic {
+            archive: self.filename.clone(),
+            page: None,
+            offset: 0,
+            kind: "id_mismatch",
+            message: format!(
+                "{}: highest event id {:#018x} does not match the file name",
+                self.filename, max_event_id
+            ),
+            bytes_skipped: 0,
+        })
+    }
+} // impl ArchiveInfo
+
+/// A decompressed fseventsd archive file.
+#[derive(Debug)]
+pub struct Archive {
+    pub pages: Vec<Page>,
+
+    pub info: ArchiveInfo,
+    /// Decompressed offset at which the gzip stream failed; the pages
+    /// before it were still parsed.
+    pub partial: Option<u64>,
+    /// Malformed pages and records; the pages around them are kept.
+    pub errors: Vec<ParseError>,
+}
+
+impl Archive {
+    /// Reads and parses the archive at `path`.
+    pub fn new(path: &str) -> Result<Self, ParseError> {
+        // timestamp & filename
+        let info = ArchiveInfo::from_path(path)?;
+        let fd = fs::File::open(path)?;
+        let mut archive = Self::from_reader(fd, &info.filename, info.mtime, info.ctime)?;
+        archive.info.volume_uuid = info.volume_uuid;
+        Ok(archive)
+    }
+
+    /// Parses a gzip compressed archive from memory. The archive gets an empty
+    /// file name and epoch timestamps.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        Self::from_reader(bytes, "", UNIX_EPOCH, UNIX_EPOCH)
+    }
+
+    /// Parses a gzip compressed archive from `reader`, e.g. a file inside a
+    /// tar/zip or a disk image, labelled with the given name and timestamps.
+    ///
+    /// Fails only when nothing can be decompressed. A malformed record is
+    /// added to `errors` and the rest of its page is skipped; a malformed page
+    /// is added to `errors` and ends the archive, keeping the pages before it.
+    /// An archive that holds no pages at all parses to an `Archive` without
+    /// pages. A gzip stream that breaks off is kept up to the failure and
+    /// marked `partial`, with its last page cut short.
+    pub fn from_reader<R: Read>(
+        reader: R,
+        filename: &str,
+        mtime: SystemTime,
+        ctime: SystemTime,
+    ) -> Result<Self, ParseError> {
+        let info = ArchiveInfo {
+            filename: String::from(filename),
+            mtime,
+            ctime,
+            volume_uuid: None,
+            source_offset: None,
+            last_event_id_hint: parse_archive_name(filename),
+        };
+
         /*
          * pages
          */
         // uncompress
         let mut buf = Vec::new();
-        let fd = fs::File::open(path)?;
-        let mut decoder = MultiGzDecoder::new(fd);
-        decoder.read_to_end(&mut buf)?;
-        // println!("uncompressed size: {} {}", filename, buf.len());
+        let mut decoder = MultiGzDecoder::new(reader);
+        let partial = match decoder.read_to_end(&mut buf) {
+            Ok(_) => None,
+            Err(e) if buf.is_empty() => {
+                return Err(ParseError::Gzip {
+                    archive: info.filename,
+                    offset: 0,
+                    error: e,
+                })
+            }
+            Err(_) => Some(buf.len() as u64),
+        };
 
         // parse all pages
         let mut pages = vec![];
+        let mut errors = vec![];
         let mut offset: usize = 0;
         while offset < buf.len() {
-            match Page::new(&buf[offset..]) {
-                Ok((page, consumed)) => {
+            match Page::parse(&buf[offset..], partial.is_some()) {
+                Ok((mut page, consumed, error)) => {
+                    if let Some(e) = error {
+                        errors.push(e.at(&info.filename, pages.len(), offset as u64));
+                    }
+                    page.entries
+                        .iter_mut()
+                        .for_each(|e| e.end_offset += offset as u64);
                     offset += consumed;
-                    // println!("parse page succeeded: {:?}, entry count: {}, page consumed: {}, stream left: {}",
-                    //     page.header, page.entries.len(), consumed, buf.len() - offset);
                     pages.push(page);
                 }
                 Err(e) => {
-                    println!(
-                        "encountered error when parsing page, move to next archive: {:?}",
-                        e
-                    );
+                    errors.push(e.at(&info.filename, pages.len(), offset as u64));
                     break;
                 }
             }
@@ -100,13 +275,20 @@ impl Archive {
 
         Ok(Archive {
             pages,
-            filename,
-            mtime: metadata.modified()?,
-            ctime: metadata.created()?,
+            info,
+            partial,
+            errors,
         })
     }
+
+    /// Streams the entries of a gzip compressed archive one record at a time,
+    /// holding at most one decompressed page in memory.
+    pub fn entries<R: Read>(reader: R) -> EntryIterator<MultiGzDecoder<R>> {
+        EntryIterator::new(MultiGzDecoder::new(reader)).with_gzip()
+    }
 } // impl Archive
 
+/// A page of records sharing one header.
 #[derive(Debug)]
 pub struct Page {
     pub header: PageHeader,
@@ -114,95 +296,525 @@ pub struct Page {
 }
 
 impl Page {
-    // usize consumed
-    pub fn new(mem: &[u8]) -> Result<(Self, usize), Box<dyn std::error::Error>> {
-        // find page magic
-        let offset = mem.windows(4).position(|window| window == b"1SLD" || window == b"2SLD" || window == b"3SLD");
-        let mut offset = offset.unwrap_or(usize::MAX);
-        if offset > mem.len() {
-            return Err(Box::new(ParseError::NoPageFound));
+    /// Parses the first page found in `mem`, returning it with the number of bytes consumed.
+    pub fn new(mem: &[u8]) -> Result<(Self, usize), ParseError> {
+        match Self::parse(mem, false)? {
+            (_, _, Some(e)) => Err(e),
+            (page, consumed, None) => Ok((page, consumed)),
         }
+    }
+
+    // with `truncated`, a page running past the end of mem keeps the records
+    // read before the cut; a malformed record ends the page early and is
+    // returned with the records before it
+    fn parse(mem: &[u8], truncated: bool) -> Result<(Self, usize, Option<ParseError>), ParseError> {
+        // find page magic
+        let start = match find_page_magic(mem) {
+            Some(o) => o,
+            None => {
+                return Err(ParseError::NoPageFound {
+                    archive: String::new(),
+                    offset: 0,
+                })
+            }
+        };
 
         // parse header
-        let header = PageHeader::new(&mem[offset..])?;
+        let cut = truncated
+            && PageHeader::parse(&mem[start..])
+                .map(|h| start + h.stream_size as usize > mem.len())
+                .unwrap_or(false);
+        let header = match cut {
+            true => PageHeader::parse(&mem[start..]),
+            false => PageHeader::new(&mem[start..]),
+        }
+        .map_err(|e| e.at("", 0, start as u64))?;
         if matches!(header.version, Version::Unknown) {
-            return Err(Box::new(ParseError::UnsupportedVersion));
+            return Err(ParseError::UnsupportedVersion {
+                archive: String::new(),
+                page: 0,
+                offset: start as u64,
+            });
         }
 
         // parse entries by length
-        // println!("parsing entries in page, size: {}", header.stream_size);
-        offset += PageHeader::len(); // skip header
+        let end = mem.len().min(start + header.stream_size as usize);
+        let mut offset = start + PageHeader::len(); // skip header
         let mut entries = vec![];
-        while offset < header.stream_size as usize && offset < mem.len() - 1 {
-            if let Some(path_len) = mem[offset..].iter().position(|&r| r == 0) {
-                /*
-                 * | full path | end with 0x00
-                 * | event id | 8 bytes
-                 * | event flags | 4 bytes
-                 * | node id | 8 bytes (version >= v2)
-                 * | unknown | 4 bytes (version >= v3)
-                 */
-
-                // path can be empty? offset == end_offset
-                let end_offset = offset + path_len;
-                let tail_len = match header.version {
-                    Version::V3 => 24, 
-                    Version::V2 => 20,
-                    _ => 12,
-                };
-                if end_offset + tail_len >= mem.len() {
-                    // other attributes
-                    println!(
-                        "invalid record for path, stop parsing page: {:?}",
-                        &mem[offset..end_offset + 1]
-                    );
+        let mut error = None;
+        while offset < end {
+            match Entry::parse(&mem[offset..end], header.version) {
+                Ok((mut entry, consumed)) => {
+                    offset += consumed;
+                    entry.end_offset = offset as u64;
+                    entries.push(entry);
+                }
+                // last record was cut off
+                Err(_) if cut => {
+                    offset = end;
+                    break;
+                }
+                Err(e) => {
+                    error = Some(e.at("", 0, offset as u64));
+                    offset = end;
                     break;
                 }
+            }
+        }
 
-                let full_path = String::from_utf8_lossy(&mem[offset..end_offset]).into_owned();
-                offset = end_offset + 1; // skip 0x00
-                                         // println!("found path: {}", full_path);
+        Ok((Page { header, entries }, offset, error)) // mem len actually consumed
+    }
+} // impl Page
 
-                // event id
-                let event_id = u64::from_le_bytes(mem[offset..offset + 8].try_into()?);
-                offset += 8;
-                // println!("event id: {}", event_id);
+/// Pulls entries out of a decompressed page stream one record at a time.
+///
+/// Only the page currently being read is kept in memory. A malformed record
+/// is yielded as an error and the rest of its page is skipped; a malformed
+/// page or a failing stream is yielded as an error and ends the iteration.
+///
+/// With [`EntryIterator::with_recovery`] the iterator instead scans forward
+/// for the next page magic or plausible record and keeps going, tagging the
+/// entries found that way as `recovered`.
+pub struct EntryIterator<R: Read> {
+    source: Source<R>,
+    archive: String,
+    gzip: bool,    // read errors come from decompression
+    recover: bool, // resynchronize after corrupt data
 
-                // flags
-                let flags = u32::from_le_bytes(mem[offset..offset + 4].try_into()?);
-                offset += 4;
-                // println!("event flags: {}", flags);
+    version: Version,
+    page: Vec<u8>,       // current page body, header excluded
+    offset: usize,       // read offset in current page body
+    page_offset: u64,    // stream offset of current page body
+    attempt_offset: u64, // stream offset the current page read started at
+    recovering: bool,    // rest of current page is parsed best-effort
+    finished: bool,
 
-                // skip node id, unknown column
-                match header.version {
-                    Version::V2 => offset += 8,
-                    Version::V3 => offset += 12,
-                    _ => {}
-                }
+    pub page_count: usize,
+    pub entry_count: usize,
+    /// Lowest and highest event ids seen so far.
+    pub min_event_id: Option<u64>,
+    pub max_event_id: Option<u64>,
+    /// Undefined flag bits seen so far, and the number of entries having any.
+    pub unknown_flags: u32,
+    pub unknown_flags_count: usize,
+    /// Parts of the stream skipped so far, with the reason.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Decompressed offset at which the gzip stream failed; the bytes before
+    /// it were still parsed.
+    pub partial: Option<u64>,
+}
 
-                // new entry generated
-                entries.push(Entry {
-                    full_path,
-                    event_id,
-                    flags: EventFlag::from_bits_truncate(flags),
+impl<R: Read> EntryIterator<R> {
+    /// Iterates the pages of an already decompressed stream.
+    pub fn new(reader: R) -> Self {
+        EntryIterator {
+            source: Source {
+                reader,
+                pending: vec![],
+                offset: 0,
+            },
+            archive: String::new(),
+            gzip: false,
+            recover: false,
+            version: Version::Unknown,
+            page: vec![],
+            offset: 0,
+            page_offset: 0,
+            attempt_offset: 0,
+            recovering: false,
+            finished: false,
+            page_count: 0,
+            entry_count: 0,
+            min_event_id: None,
+            max_event_id: None,
+            unknown_flags: 0,
+            unknown_flags_count: 0,
+            diagnostics: vec![],
+            partial: None,
+        }
+    }
+
+    /// Names the archive reported in errors.
+    pub fn with_archive_name(mut self, archive: &str) -> Self {
+        self.archive = String::from(archive);
+        self
+    }
+
+    /// Reports read errors as decompression failures, for a reader that
+    /// decompresses a gzip stream.
+    pub fn with_gzip(mut self) -> Self {
+        self.gzip = true;
+        self
+    }
+
+    /// Resynchronizes after corrupt records and pages instead of skipping
+    /// the rest of the page or archive.
+    pub fn with_recovery(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    // load next page into buffer, false if stream ended
+    fn next_page(&mut self) -> Result<bool, ParseError> {
+        loop {
+            self.attempt_offset = self.source.offset;
+
+            // find page magic, skipping bytes in front of it
+            let mut header = [0u8; 12];
+            let mut filled = self.read_full(&mut header)?;
+            if filled == 0 {
+                return Ok(false);
+            }
+            while filled < 4 || find_page_magic(&header[..4]) != Some(0) {
+                if filled < 4 {
+                    return Err(ParseError::NoPageFound {
+                        archive: self.archive.clone(),
+                        offset: self.source.offset - filled as u64,
+                    });
+                }
+                header.copy_within(1..filled, 0);
+                filled -= 1;
+                filled += self.read_full(&mut header[filled..])?;
+            }
+            let header_offset = self.source.offset - filled as u64;
+            if header_offset > self.attempt_offset {
+                self.diagnostics.push(Diagnostic {
+                    archive: self.archive.clone(),
+                    page: None,
+                    offset: self.attempt_offset,
+                    kind: "unexpected_data",
+                    message: format!(
+                        "{}: skipped data in front of page at offset {}",
+                        self.archive, header_offset
+                    ),
+                    bytes_skipped: header_offset - self.attempt_offset,
                 });
-            } else {
-                // no 0x00 any more
-                offset = mem.len();
+            }
+
+            // parse header
+            let header_bytes = header;
+            let header = PageHeader::parse(&header[..filled])
+                .map_err(|e| e.at(&self.archive, self.page_count, header_offset))?;
+            let bad_size = ParseError::BadStreamSize {
+                archive: self.archive.clone(),
+                page: self.page_count,
+                offset: header_offset,
+                size: header.stream_size,
+            };
+            let body_len = match (header.stream_size as usize).checked_sub(PageHeader::len()) {
+                Some(l) => l,
+                None if self.recover => {
+                    // look for another magic right after this one
+                    self.diagnostics.push(Diagnostic::from_error(&bad_size, 1));
+                    self.source.unread(&header_bytes[1..filled]);
+                    continue;
+                }
+                None => return Err(bad_size),
+            };
+
+            // read page body
+            self.page.clear();
+            self.offset = 0;
+            self.page_offset = self.source.offset;
+            self.version = header.version;
+            self.recovering = false;
+            if let Some(e) = self.source.read_to(&mut self.page, body_len) {
+                let e = self.read_error(e);
+                if self.page.is_empty() {
+                    return Err(e);
+                }
+
+                // keep the part of the page decompressed before the failure
+                self.diagnostics.push(self.partial_diagnostic(&e));
+                self.finished = true;
+            } else if self.page.len() < body_len {
+                if !self.recover {
+                    return Err(bad_size);
+                }
+                // stream ends inside the page, parse what is there
+                self.diagnostics.push(Diagnostic::from_error(&bad_size, 0));
+                self.recovering = true;
+            }
+
+            self.page_count += 1;
+            return Ok(true);
+        }
+    }
+
+    // offset of the next plausible record in the current page at or after
+    // `from`, cutting the page short at an embedded page magic
+    fn resync(&mut self, from: usize) -> Option<usize> {
+        for p in from..self.page.len() {
+            if find_page_magic(&self.page[p..self.page.len().min(p + 4)]) == Some(0) {
+                // page size was wrong, the rest belongs to the next page
+                self.source.unread(&self.page[p..]);
+                self.page.truncate(p);
+                return None;
+            }
+            if let Some(consumed) = plausible_record(&self.page[p..], self.version) {
+                let next = p + consumed;
+                if next == self.page.len()
+                    || plausible_record(&self.page[next..], self.version).is_some()
+                {
+                    return Some(p);
+                }
+            }
+        }
+        None
+    }
+
+    // a page magic where a record should start means the page size was
+    // wrong and the rest belongs to the next page
+    fn split_embedded_page(&mut self) {
+        let end = self.page.len().min(self.offset + 4);
+        if find_page_magic(&self.page[self.offset..end]) != Some(0) {
+            return;
+        }
+
+        let offset = self.page_offset + self.offset as u64;
+        self.diagnostics.push(Diagnostic {
+            archive: self.archive.clone(),
+            page: Some(self.page_count - 1),
+            offset,
+            kind: "embedded_page",
+            message: format!(
+                "{}: page {} runs into another page at offset {}",
+                self.archive,
+                self.page_count - 1,
+                offset
+            ),
+            bytes_skipped: 0,
+        });
+        self.source.unread(&self.page[self.offset..]);
+        self.page.truncate(self.offset);
+    }
+
+    fn read_full(&mut self, buf: &mut [u8]) -> Result<usize, ParseError> {
+        let (filled, error) = self.source.read_full(buf);
+        match error {
+            Some(e) => Err(self.read_error(e)),
+            None => Ok(filled),
+        }
+    }
+
+    fn read_error(&mut self, error: std::io::Error) -> ParseError {
+        match self.gzip {
+            true => {
+                self.partial = Some(self.source.offset);
+                ParseError::Gzip {
+                    archive: self.archive.clone(),
+                    offset: self.source.offset,
+                    error,
+                }
+            }
+            false => ParseError::IoError(error),
+        }
+    }
+
+    fn partial_diagnostic(&self, error: &ParseError) -> Diagnostic {
+        Diagnostic {
+            archive: self.archive.clone(),
+            page: None,
+            offset: self.source.offset,
+            kind: "partial_archive",
+            message: format!("{}, archive is partial", error),
+            bytes_skipped: 0,
+        }
+    }
+} // impl EntryIterator
+
+impl<R: Read> Iterator for EntryIterator<R> {
+    type Item = Result<Entry, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.recover && self.offset < self.page.len() {
+                self.split_embedded_page();
+            }
+            if self.offset < self.page.len() {
+                let mem = &self.page[self.offset..];
+                let parsed = match Entry::parse(mem, self.version) {
+                    // a garbled path means the record boundary was lost
+                    Ok(_) if self.recover && !plausible_path(mem) => Err(ParseError::InvalidPath {
+                        archive: String::new(),
+                        page: 0,
+                        offset: 0,
+                    }),
+                    parsed => parsed,
+                };
+                match parsed {
+                    Ok((mut entry, consumed)) => {
+                        self.offset += consumed;
+                        self.entry_count += 1;
+                        self.min_event_id = Some(
+                            self.min_event_id
+                                .map_or(entry.event_id, |id| id.min(entry.event_id)),
+                        );
+                        self.max_event_id = self.max_event_id.max(Some(entry.event_id));
+                        entry.end_offset = self.page_offset + self.offset as u64;
+                        let unknown = entry.flags_raw & !flags::defined_flags(entry.version);
+                        if unknown != 0 {
+                            self.unknown_flags |= unknown;
+                            self.unknown_flags_count += 1;
+                        }
+                        entry.recovered = self.recovering;
+                        return Some(Ok(entry));
+                    }
+                    Err(e) => {
+                        let offset = self.page_offset + self.offset as u64;
+                        let e = e.at(&self.archive, self.page_count - 1, offset);
+                        let resync = match self.recover {
+                            true => self.resync(self.offset + 1),
+                            false => None,
+                        };
+
+                        // skip to the next plausible record or the end of page
+                        let next = resync.unwrap_or(self.page.len());
+                        let skipped = (next - self.offset) as u64;
+                        self.diagnostics.push(Diagnostic::from_error(&e, skipped));
+                        if resync.is_some() {
+                            self.recovering = true;
+                            self.diagnostics.push(Diagnostic {
+                                archive: self.archive.clone(),
+                                page: Some(self.page_count - 1),
+                                offset: self.page_offset + next as u64,
+                                kind: "resynchronized",
+                                message: format!(
+                                    "{}: resumed best-effort parsing of page {} at offset {}",
+                                    self.archive,
+                                    self.page_count - 1,
+                                    self.page_offset + next as u64
+                                ),
+                                bytes_skipped: 0,
+                            });
+                        }
+                        self.offset = next;
+                        return Some(Err(e));
+                    }
+                }
+            }
+
+            if self.finished {
+                return None;
+            }
+            match self.next_page() {
+                Ok(true) => {}
+                Ok(false) => self.finished = true,
+                Err(e) => {
+                    // drop whatever part of the page was read
+                    let skipped = self.source.offset - self.attempt_offset;
+                    self.diagnostics.push(match e {
+                        ParseError::Gzip { .. } if self.entry_count > 0 => {
+                            self.partial_diagnostic(&e)
+                        }
+                        _ => Diagnostic::from_error(&e, skipped),
+                    });
+                    self.page.clear();
+                    self.offset = 0;
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+} // impl Iterator for EntryIterator
+
+// reader with push back, counting the bytes read
+struct Source<R: Read> {
+    reader: R,
+    pending: Vec<u8>, // bytes pushed back, read before the reader
+    offset: u64,      // bytes read so far
+}
+
+impl<R: Read> Source<R> {
+    // read until buf is full, the stream ends or fails
+    fn read_full(&mut self, buf: &mut [u8]) -> (usize, Option<std::io::Error>) {
+        let mut filled = self.pending.len().min(buf.len());
+        buf[..filled].copy_from_slice(&self.pending[..filled]);
+        self.pending.drain(..filled);
+
+        let mut error = None;
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+        self.offset += filled as u64;
+        (filled, error)
+    }
+
+    // append up to len bytes to buf
+    fn read_to(&mut self, buf: &mut Vec<u8>, len: usize) -> Option<std::io::Error> {
+        let mut chunk = [0u8; 8192];
+        while buf.len() < len {
+            let want = chunk.len().min(len - buf.len());
+            let (filled, error) = self.read_full(&mut chunk[..want]);
+            buf.extend_from_slice(&chunk[..filled]);
+            if error.is_some() {
+                return error;
+            }
+            if filled < want {
                 break;
             }
         }
+        None
+    }
 
-        Ok((Page { header, entries }, offset)) // mem len actually consumed
+    // push bytes back to be read again
+    fn unread(&mut self, data: &[u8]) {
+        let mut pending = data.to_vec();
+        pending.append(&mut self.pending);
+        self.pending = pending;
+        self.offset -= data.len() as u64;
     }
-} // impl Page
+} // impl Source
+
+// the raw path of the record at the start of mem, up to its terminating
+// 0x00, is non-empty printable utf-8
+fn plausible_path(mem: &[u8]) -> bool {
+    let path = match mem.iter().position(|&b| b == 0) {
+        Some(path_len) => &mem[..path_len],
+        None => return false,
+    };
+    !path.is_empty() && std::str::from_utf8(path).is_ok_and(|p| !p.chars().any(|c| c.is_control()))
+}
+
+// length of the record at the start of mem if it looks like a genuine one:
+// plausible path, non-zero event id and only known flags set
+pub(crate) fn plausible_record(mem: &[u8], version: Version) -> Option<usize> {
+    let (entry, consumed) = Entry::parse(mem, version).ok()?;
+    if !plausible_path(mem) {
+        return None;
+    }
+    let flags = entry.flags_raw;
+    let plausible = entry.event_id != 0 && flags != 0 && flags & !EventFlag::all().bits() == 0;
+    match plausible {
+        true => Some(consumed),
+        false => None,
+    }
+}
 
+// offset of the first page magic in mem
+pub(crate) fn find_page_magic(mem: &[u8]) -> Option<usize> {
+    mem.windows(4)
+        .position(|window| window == b"1SLD" || window == b"2SLD" || window == b"3SLD")
+}
+
+/// Page header: magic and size of the page in bytes, header included.
 #[derive(Debug)]
 pub struct PageHeader {
-    version: Version,
-    stream_size: u32,
+    pub version: Version,
+    pub stream_size: u32,
 }
-#[derive(Debug)]
+
+/// Page format version, taken from the page magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Version {
     Unknown,
     V1,
@@ -210,10 +822,32 @@ pub enum Version {
     V3,
 }
 impl PageHeader {
-    pub fn new(mem: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Parses a page header from the start of `mem`, which must hold the whole page.
+    pub fn new(mem: &[u8]) -> Result<Self, ParseError> {
+        let header = Self::parse(mem)?;
+        if header.stream_size as usize > mem.len() || (header.stream_size as usize) < Self::len() {
+            return Err(ParseError::BadStreamSize {
+                archive: String::new(),
+                page: 0,
+                offset: 0,
+                size: header.stream_size,
+            });
+        }
+
+        Ok(header)
+    }
+
+    /// Parses a page header from the start of `mem` without checking the page size.
+    pub fn parse(mem: &[u8]) -> Result<Self, ParseError> {
+        let invalid = ParseError::InvalidHeader {
+            archive: String::new(),
+            page: 0,
+            offset: 0,
+        };
+
         // validate len
         if mem.len() < Self::len() {
-            return Err(Box::new(ParseError::InvalidHeader));
+            return Err(invalid);
         }
 
         // parse version
@@ -226,14 +860,11 @@ impl PageHeader {
             version = Version::V3;
         }
         if matches!(version, Version::Unknown) {
-            return Err(Box::new(ParseError::InvalidHeader));
+            return Err(invalid);
         }
 
         // parse len
-        let len = u32::from_le_bytes(mem[8..12].try_into()?);
-        if len as usize > mem.len() {
-            return Err(Box::new(ParseError::InvalidHeader));
-        }
+        let len = read_u32(mem, 8);
 
         Ok(PageHeader {
             version,
@@ -241,19 +872,202 @@ impl PageHeader {
         })
     }
 
+    /// Size of the header in bytes.
     pub fn len() -> usize {
         12
     }
 } // impl PageHeader
 
-#[derive(Debug)]
+/// A single fsevents record.
+#[derive(Debug, Clone)]
 pub struct Entry {
     pub full_path: String,
     pub event_id: u64,
     pub flags: EventFlag,
+    pub flags_raw: u32,        // flags as stored, including undefined bits
+    pub node_id: Option<u64>,  // inode / file id, version >= v2
+    pub extra_v3: Option<u32>, // trailing unknown field, version >= v3
+    pub recovered: bool,       // found by resynchronizing after corrupt data
+    pub version: Version,      // version of the page the record was read from
+    pub end_offset: u64,       // offset past the record in the decompressed stream
+    /// Window the event happened in, when estimated from time anchors.
+    pub time_lower_bound: Option<SystemTime>,
+    pub time_upper_bound: Option<SystemTime>,
+}
+
+impl Entry {
+    /// Parses the record at the start of `mem`, returning it with the number of bytes consumed.
+    pub fn parse(mem: &[u8], version: Version) -> Result<(Self, usize), ParseError> {
+        /*
+         * | full path | end with 0x00
+         * | event id | 8 bytes
+         * | event flags | 4 bytes
+         * | node id | 8 bytes (version >= v2)
+         * | unknown | 4 bytes (version >= v3)
+         */
+        let path_len = match mem.iter().position(|&r| r == 0) {
+            Some(l) => l,
+            None => {
+                return Err(ParseError::UnterminatedPath {
+                    archive: String::new(),
+                    page: 0,
+                    offset: 0,
+                })
+            }
+        };
+
+        // path can be empty? offset == end_offset
+        let end_offset = path_len;
+        let tail_len = match version {
+            Version::V3 => 24,
+            Version::V2 => 20,
+            _ => 12,
+        };
+        if end_offset + tail_len >= mem.len() {
+            // other attributes
+            return Err(ParseError::TruncatedRecord {
+                archive: String::new(),
+                page: 0,
+                offset: 0,
+            });
+        }
+
+        let full_path = String::from_utf8_lossy(&mem[..end_offset]).into_owned();
+        let mut offset = end_offset + 1; // skip 0x00
+
+        // event id
+        let event_id = read_u64(mem, offset);
+        offset += 8;
+
+        // flags
+        let flags = read_u32(mem, offset);
+        offset += 4;
+
+        // node id (version >= v2)
+        let node_id = match version {
+            Version::V2 | Version::V3 => {
+                let node_id = read_u64(mem, offset);
+                offset += 8;
+                Some(node_id)
+            }
+            _ => None,
+        };
+
+        // unknown column (version >= v3)
+        let extra_v3 = match version {
+            Version::V3 => {
+                let extra = read_u32(mem, offset);
+                offset += 4;
+                Some(extra)
+            }
+            _ => None,
+        };
+
+        Ok((
+            Entry {
+                full_path,
+                event_id,
+                flags: EventFlag::from_bits_truncate(flags & flags::defined_flags(version)),
+                flags_raw: flags,
+                node_id,
+                extra_v3,
+                recovered: false,
+                version,
+                end_offset: 0,
+                time_lower_bound: None,
+                time_upper_bound: None,
+            },
+            offset,
+        ))
+    }
+
+    /// Normalized event types of the record, decoded with the flag layout of
+    /// its page version.
+    pub fn event_types(&self) -> Vec<&'static str> {
+        flags::event_types(self.flags_raw, self.version)
+    }
+
+    /// The entry as the Python FSEventsParser presents it.
+    pub fn summary(&self) -> EventSummary {
+        let item_type = flags::item_type(self.flags_raw, self.version);
+        let type_events: Vec<&str> = flags::flag_table(self.version)
+            .iter()
+            .filter(|def| def.item_type.is_some())
+            .map(|def| def.event_type)
+            .collect();
+        let mask = self
+            .event_types()
+            .into_iter()
+            .filter(|t| !type_events.contains(t))
+            .map(|t| format!("{};", t))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        EventSummary {
+            id_hex: format!("{:#x}", self.event_id),
+            filename: String::from(self.full_path.rsplit('/').next().unwrap_or("")),
+            item_type: String::from(item_type.unwrap_or("")),
+            // FSEventsParser reads the flag bytes big-endian
+            flags_hex: format!("{:#010x}", self.flags_raw.swap_bytes()),
+            mask,
+        }
+    }
+
+    /// Flag bits set in the record that its page version does not define,
+    /// one value per bit.
+    pub fn unknown_flags(&self) -> Vec<u32> {
+        let unknown = self.flags_raw & !flags::defined_flags(self.version);
+        (0..32)
+            .map(|bit| 1u32 << bit)
+            .filter(|bit| unknown & bit != 0)
+            .collect()
+    }
+} // impl Entry
+
+#[cfg(test)]
+impl Entry {
+    /// A `2SLD` record of `path` without node id, for tests.
+    pub(crate) fn for_test(path: &str, event_id: u64, flags: EventFlag) -> Self {
+        Entry {
+            full_path: String::from(path),
+            event_id,
+            flags,
+            flags_raw: flags.bits(),
+            node_id: None,
+            extra_v3: None,
+            recovered: false,
+            version: Version::V2,
+            end_offset: 0,
+            time_lower_bound: None,
+            time_upper_bound: None,
+        }
+    }
+} // impl Entry
+
+/// Friendly fields of an entry, laid out like the columns of FSEventsParser.
+#[derive(Debug, Clone)]
+pub struct EventSummary {
+    pub id_hex: String,    // event id in hex, e.g. 0x1a2b
+    pub filename: String,  // last component of the path
+    pub item_type: String, // File, Folder, Symlink or HardLink
+    pub flags_hex: String, // raw flag bytes in hex, big-endian, e.g. 0x01008000
+    pub mask: String,      // event types, e.g. "Created; Modified; Renamed;"
+}
+
+fn read_u64(mem: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&mem[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_u32(mem: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&mem[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
 }
 
 bitflags! {
+    /// Event flags as stored in the disk log (`FSE_*`).
     pub struct EventFlag : u32 {
         const FSE_NONE = 0x00000000;
 
@@ -288,25 +1102,330 @@ bitflags! {
     }
 }
 
+impl EventFlag {
+    /// Short name of every flag bit, as used for output columns.
+    pub const NAMES: [(&'static str, EventFlag); 26] = [
+        ("created_file", EventFlag::FSE_CREATE_FILE),
+        ("deleted", EventFlag::FSE_DELETE),
+        ("stat_changed", EventFlag::FSE_STAT_CHANGED),
+        ("renamed", EventFlag::FSE_RENAME),
+        ("content_modified", EventFlag::FSE_CONTENT_MODIFIED),
+        ("exchanged", EventFlag::FSE_EXCHANGE),
+        ("finder_info_changed", EventFlag::FSE_FINDER_INFO_CHANGED),
+        ("created_dir", EventFlag::FSE_CREATE_DIR),
+        ("owner_changed", EventFlag::FSE_CHOWN),
+        ("xattr_modified", EventFlag::FSE_XATTR_MODIFIED),
+        ("xattr_removed", EventFlag::FSE_XATTR_REMOVED),
+        ("docid_created", EventFlag::FSE_DOCID_CREATED),
+        ("docid_changed", EventFlag::FSE_DOCID_CHANGED),
+        ("unmount_pending", EventFlag::FSE_UNMOUNT_PENDING),
+        ("cloned", EventFlag::FSE_CLONE),
+        ("mode_clone", EventFlag::FSE_MODE_CLONE),
+        ("truncated_path", EventFlag::FSE_TRUNCATED_PATH),
+        ("remote_dir_event", EventFlag::FSE_REMOTE_DIR_EVENT),
+        ("mode_last_hlink", EventFlag::FSE_MODE_LAST_HLINK),
+        ("mode_hlink", EventFlag::FSE_MODE_HLINK),
+        ("is_symlink", EventFlag::FSE_IS_SYMLINK),
+        ("is_file", EventFlag::FSE_IS_FILE),
+        ("is_dir", EventFlag::FSE_IS_DIR),
+        ("mounted", EventFlag::FSE_MOUNT),
+        ("unmounted", EventFlag::FSE_UNMOUNT),
+        ("end_transaction", EventFlag::FSE_END_TRANSACTION),
+    ];
+
+    /// Short names of the flags set.
+    pub fn names(&self) -> Vec<&'static str> {
+        EventFlag::NAMES
+            .iter()
+            .filter(|(_, flag)| self.contains(*flag))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Flag named either by its short name (`deleted`) or by its constant
+    /// name, with or without the `FSE_` prefix (`DELETE`), in any case.
+    pub fn from_name(name: &str) -> Option<EventFlag> {
+        let name = name.to_uppercase();
+        let constant = match name.starts_with("FSE_") {
+            true => name.clone(),
+            false => format!("FSE_{}", name),
+        };
+        EventFlag::NAMES
+            .iter()
+            .find(|(short, flag)| short.to_uppercase() == name || format!("{:?}", flag) == constant)
+            .map(|(_, flag)| *flag)
+    }
+}
+
+/// A part of an archive that could not be parsed.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub archive: String,
+    pub page: Option<usize>, // page index within the archive, if known
+    pub offset: u64,         // offset in the decompressed stream
+    pub kind: &'static str,
+    pub message: String,
+    pub bytes_skipped: u64, // decompressed bytes left unparsed
+}
+
+impl Diagnostic {
+    /// Describes `error`, which caused `bytes_skipped` bytes to be left unparsed.
+    pub fn from_error(error: &ParseError, bytes_skipped: u64) -> Self {
+        let (archive, page, offset) = match error {
+            ParseError::NoPageFound { archive, offset } => (archive.clone(), None, *offset),
+            ParseError::InvalidHeader {
+                archive,
+                page,
+                offset,
+            }
+            | ParseError::UnsupportedVersion {
+                archive,
+                page,
+                offset,
+            }
+            | ParseError::BadStreamSize {
+                archive,
+                page,
+                offset,
+                ..
+            }
+            | ParseError::TruncatedRecord {
+                archive,
+                page,
+                offset,
+            }
+            | ParseError::UnterminatedPath {
+                archive,
+                page,
+                offset,
+            }
+            | ParseError::InvalidPath {
+                archive,
+                page,
+                offset,
+            } => (archive.clone(), Some(*page), *offset),
+            ParseError::Gzip {
+                archive, offset, ..
+            } => (archive.clone(), None, *offset),
+            ParseError::IoError(_) => (String::new(), None, 0),
+        };
+
+        Diagnostic {
+            archive,
+            page,
+            offset,
+            kind: error.kind(),
+            message: error.to_string(),
+            bytes_skipped,
+        }
+    }
+} // impl Diagnostic
+
+/// Errors raised while parsing an archive.
+///
+/// Parse failures carry the archive name, the index of the page within the
+/// archive and the offset in the decompressed stream where they occurred.
 #[derive(Debug)]
 pub enum ParseError {
-    NoPageFound,
-    InvalidHeader,
-    UnsupportedVersion,
+    /// No page magic left in the stream.
+    NoPageFound {
+        archive: String,
+        offset: u64,
+    },
+    /// Page header too short or with an unknown magic.
+    InvalidHeader {
+        archive: String,
+        page: usize,
+        offset: u64,
+    },
+    UnsupportedVersion {
+        archive: String,
+        page: usize,
+        offset: u64,
+    },
+    /// Page size smaller than its header or larger than the data left.
+    BadStreamSize {
+        archive: String,
+        page: usize,
+        offset: u64,
+        size: u32,
+    },
+    /// Record fields run past the end of the page.
+    TruncatedRecord {
+        archive: String,
+        page: usize,
+        offset: u64,
+    },
+    /// Record path without 0x00 terminator before the end of the page.
+    UnterminatedPath {
+        archive: String,
+        page: usize,
+        offset: u64,
+    },
+    /// Record path that is not printable utf-8, only checked when recovering.
+    InvalidPath {
+        archive: String,
+        page: usize,
+        offset: u64,
+    },
+    /// Decompression failed, `offset` counts the bytes decompressed before.
+    Gzip {
+        archive: String,
+        offset: u64,
+        error: std::io::Error,
+    },
     IoError(std::io::Error),
 }
+
+impl ParseError {
+    /// Short machine readable name of the error variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::NoPageFound { .. } => "no_page_found",
+            ParseError::InvalidHeader { .. } => "invalid_header",
+            ParseError::UnsupportedVersion { .. } => "unsupported_version",
+            ParseError::BadStreamSize { .. } => "bad_stream_size",
+            ParseError::TruncatedRecord { .. } => "truncated_record",
+            ParseError::UnterminatedPath { .. } => "unterminated_path",
+            ParseError::InvalidPath { .. } => "invalid_path",
+            ParseError::Gzip { .. } => "gzip",
+            ParseError::IoError(_) => "io",
+        }
+    }
+
+    // fill in the archive and page, shifting the offset by base
+    fn at(self, archive: &str, page: usize, base: u64) -> Self {
+        let archive = String::from(archive);
+        match self {
+            ParseError::NoPageFound { offset, .. } => ParseError::NoPageFound {
+                archive,
+                offset: base + offset,
+            },
+            ParseError::InvalidHeader { offset, .. } => ParseError::InvalidHeader {
+                archive,
+                page,
+                offset: base + offset,
+            },
+            ParseError::UnsupportedVersion { offset, .. } => ParseError::UnsupportedVersion {
+                archive,
+                page,
+                offset: base + offset,
+            },
+            ParseError::BadStreamSize { offset, size, .. } => ParseError::BadStreamSize {
+                archive,
+                page,
+                offset: base + offset,
+                size,
+            },
+            ParseError::TruncatedRecord { offset, .. } => ParseError::TruncatedRecord {
+                archive,
+                page,
+                offset: base + offset,
+            },
+            ParseError::UnterminatedPath { offset, .. } => ParseError::UnterminatedPath {
+                archive,
+                page,
+                offset: base + offset,
+            },
+            ParseError::InvalidPath { offset, .. } => ParseError::InvalidPath {
+                archive,
+                page,
+                offset: base + offset,
+            },
+            ParseError::Gzip { offset, error, .. } => ParseError::Gzip {
+                archive,
+                offset: base + offset,
+                error,
+            },
+            ParseError::IoError(e) => ParseError::IoError(e),
+        }
+    }
+}
+
 impl std::error::Error for ParseError {}
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ParseError::NoPageFound => {
-                write!(f, "no v2 page found")
+            ParseError::NoPageFound { archive, offset } => {
+                write!(f, "{}: no page found after offset {}", archive, offset)
+            }
+            ParseError::InvalidHeader {
+                archive,
+                page,
+                offset,
+            } => {
+                write!(
+                    f,
+                    "{}: invalid header of page {} at offset {}",
+                    archive, page, offset
+                )
+            }
+            ParseError::UnsupportedVersion {
+                archive,
+                page,
+                offset,
+            } => {
+                write!(
+                    f,
+                    "{}: version of page {} at offset {} not supported",
+                    archive, page, offset
+                )
             }
-            ParseError::InvalidHeader => {
-                write!(f, "invalid header")
+            ParseError::BadStreamSize {
+                archive,
+                page,
+                offset,
+                size,
+            } => {
+                write!(
+                    f,
+                    "{}: bad size {} of page {} at offset {}",
+                    archive, size, page, offset
+                )
             }
-            ParseError::UnsupportedVersion => {
-                write!(f, "page version not supported")
+            ParseError::TruncatedRecord {
+                archive,
+                page,
+                offset,
+            } => {
+                write!(
+                    f,
+                    "{}: truncated record in page {} at offset {}",
+                    archive, page, offset
+                )
+            }
+            ParseError::UnterminatedPath {
+                archive,
+                page,
+                offset,
+            } => {
+                write!(
+                    f,
+                    "{}: non-terminated path in page {} at offset {}",
+                    archive, page, offset
+                )
+            }
+            ParseError::InvalidPath {
+                archive,
+                page,
+                offset,
+            } => {
+                write!(
+                    f,
+                    "{}: invalid path in page {} at offset {}",
+                    archive, page, offset
+                )
+            }
+            ParseError::Gzip {
+                archive,
+                offset,
+                error,
+            } => {
+                write!(
+                    f,
+                    "{}: decompression failed after {} bytes: {}",
+                    archive, offset, error
+                )
             }
             ParseError::IoError(e) => {
                 write!(f, "{}", e)
@@ -319,3 +1438,238 @@ impl From<std::io::Error> for ParseError {
         ParseError::IoError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn record(path: &[u8], event_id: u64, flags: u32, node_id: u64) -> Vec<u8> {
+        let mut record = path.to_vec();
+        record.push(0);
+        record.extend_from_slice(&event_id.to_le_bytes());
+        record.extend_from_slice(&flags.to_le_bytes());
+        record.extend_from_slice(&node_id.to_le_bytes());
+        record
+    }
+
+    fn page(body: &[u8]) -> Vec<u8> {
+        page_sized(body, (body.len() + PageHeader::len()) as u32)
+    }
+
+    fn page_sized(body: &[u8], stream_size: u32) -> Vec<u8> {
+        let mut page = b"2SLD".to_vec();
+        page.extend_from_slice(&0x12345678u32.to_le_bytes());
+        page.extend_from_slice(&stream_size.to_le_bytes());
+        page.extend_from_slice(body);
+        page
+    }
+
+    fn file(path: &str, event_id: u64) -> Vec<u8> {
+        record(path.as_bytes(), event_id, 0x00800001, event_id)
+    }
+
+    // entries and errors of an uncompressed stream, with the iterator
+    fn parse(stream: &[u8], recover: bool) -> (Vec<Entry>, Vec<ParseError>, Vec<Diagnostic>) {
+        let mut iter = EntryIterator::new(stream)
+            .with_archive_name("test")
+            .with_recovery(recover);
+        let (mut entries, mut errors) = (vec![], vec![]);
+        for result in iter.by_ref() {
+            match result {
+                Ok(entry) => entries.push(entry),
+                Err(e) => errors.push(e),
+            }
+        }
+        (entries, errors, iter.diagnostics)
+    }
+
+    fn ids(entries: &[Entry]) -> Vec<u64> {
+        entries.iter().map(|e| e.event_id).collect()
+    }
+
+    #[test]
+    fn parses_records_across_pages() {
+        let mut stream = page(&[file("a/one", 1), file("a/two", 2)].concat());
+        stream.extend(page(&file("a/three", 3)));
+
+        let (entries, errors, diagnostics) = parse(&stream, false);
+        assert!(errors.is_empty() && diagnostics.is_empty());
+        assert_eq!(ids(&entries), [1, 2, 3]);
+        assert_eq!(entries[1].full_path, "a/two");
+        assert_eq!(entries[1].node_id, Some(2));
+        assert_eq!(entries[1].version, Version::V2);
+        // end offsets count from the start of the stream
+        assert_eq!(entries[0].end_offset, 12 + 26);
+        assert_eq!(entries[2].end_offset, 2 * 12 + 26 * 2 + 28);
+    }
+
+    #[test]
+    fn truncated_record() {
+        let mut body = file("a/one", 1);
+        body.extend_from_slice(b"a/two\0\x02\0\0\0");
+        let mut stream = page(&body);
+        stream.extend(page(&file("a/three", 3)));
+
+        // the rest of the page is skipped, the next page is still read
+        let (entries, errors, diagnostics) = parse(&stream, false);
+        assert_eq!(ids(&entries), [1, 3]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), "truncated_record");
+        assert_eq!(diagnostics[0].bytes_skipped, 10);
+
+        let (entries, _, _) = parse(&stream, true);
+        assert_eq!(ids(&entries), [1, 3]);
+    }
+
+    #[test]
+    fn bad_stream_size() {
+        let mut stream = page_sized(&[], 4);
+        stream.extend(page(&file("a/one", 1)));
+
+        let (entries, errors, _) = parse(&stream, false);
+        assert!(entries.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), "bad_stream_size");
+
+        // recovery looks for the next page magic
+        let (entries, _, diagnostics) = parse(&stream, true);
+        assert_eq!(ids(&entries), [1]);
+        assert_eq!(diagnostics[0].kind, "bad_stream_size");
+
+        // a page cut short by the end of the stream
+        let body = [file("a/one", 1), file("a/two", 2)].concat();
+        let stream = page_sized(&body, 200);
+        let (entries, errors, _) = parse(&stream, false);
+        assert!(entries.is_empty());
+        assert_eq!(errors[0].kind(), "bad_stream_size");
+        let (entries, _, _) = parse(&stream, true);
+        assert_eq!(ids(&entries), [1, 2]);
+        assert!(entries.iter().all(|e| e.recovered));
+    }
+
+    #[test]
+    fn embedded_page_magic() {
+        // the first page claims the second one as part of its body
+        let first = file("a/one", 1);
+        let second = page(&file("a/two", 2));
+        let mut stream = page_sized(&first, (12 + first.len() + second.len()) as u32);
+        stream.extend(&second);
+        stream.extend(page(&file("a/three", 3)));
+
+        let (entries, _, diagnostics) = parse(&stream, true);
+        assert_eq!(ids(&entries), [1, 2, 3]);
+        assert!(diagnostics.iter().any(|d| d.kind == "embedded_page"));
+        assert_eq!(entries[1].end_offset, (12 + first.len() + 12 + 26) as u64);
+    }
+
+    #[test]
+    fn invalid_path() {
+        let body = [
+            file("a/one", 1),
+            record(&[0xff; 20], 2, 0x00800001, 2),
+            file("a/three", 3),
+        ]
+        .concat();
+        let stream = page(&body);
+
+        // without recovery the path is decoded lossily
+        let (entries, errors, _) = parse(&stream, false);
+        assert!(errors.is_empty());
+        assert_eq!(ids(&entries), [1, 2, 3]);
+        assert!(entries[1].full_path.starts_with('\u{fffd}'));
+
+        // with recovery the record is skipped up to the next plausible one
+        let (entries, errors, diagnostics) = parse(&stream, true);
+        assert_eq!(ids(&entries), [1, 3]);
+        assert_eq!(errors[0].kind(), "invalid_path");
+        assert!(entries[1].recovered);
+        assert!(diagnostics.iter().any(|d| d.kind == "resynchronized"));
+
+        // a non-utf-8 path in the last record, cut short by the page end
+        let mut body = file("a/one", 1);
+        body.extend_from_slice(&[0xff, 0x00]);
+        body.extend_from_slice(&[0x01; 12]);
+        let (entries, errors, _) = parse(&page(&body), true);
+        assert_eq!(ids(&entries), [1]);
+        assert_eq!(errors.len(), 1);
+
+        assert!(plausible_record(&file("a/one", 1), Version::V2).is_some());
+        assert!(plausible_record(&record(&[0xff; 20], 2, 1, 2), Version::V2).is_none());
+        assert!(plausible_record(&record(&[0xc3, 0xa9], 2, 1, 2), Version::V2).is_some());
+    }
+
+    #[test]
+    fn partial_gzip() {
+        let stream = [page(&file("a/one", 1)), page(&file("a/two", 2))].concat();
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&stream).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        let mut iter = Archive::entries(&gzip[..gzip.len() - 12]);
+        let entries: Vec<Entry> = iter.by_ref().filter_map(|r| r.ok()).collect();
+        assert!(!entries.is_empty());
+        assert!(iter.partial.is_some());
+        assert!(iter.diagnostics.iter().any(|d| d.kind == "partial_archive"));
+
+        let archive = Archive::from_bytes(&gzip[..gzip.len() - 12]).unwrap();
+        assert!(archive.partial.is_some());
+
+        let mut iter = Archive::entries(&gzip[..]);
+        assert_eq!(iter.by_ref().filter_map(|r| r.ok()).count(), 2);
+        assert!(iter.partial.is_none());
+    }
+
+    #[test]
+    fn summary_like_fseventsparser() {
+        let flags =
+            (EventFlag::FSE_CREATE_FILE | EventFlag::FSE_CONTENT_MODIFIED | EventFlag::FSE_IS_FILE)
+                .bits();
+        let (entry, _) =
+            Entry::parse(&record(b"a/b/c.txt", 0x1a2b, flags, 7), Version::V2).unwrap();
+        let summary = entry.summary();
+        assert_eq!(summary.id_hex, "0x1a2b");
+        assert_eq!(summary.filename, "c.txt");
+        assert_eq!(summary.item_type, "File");
+        // Created is 0x01000000 and FileEvent 0x00008000 in the byte order
+        // FSEventsParser shows
+        assert_eq!(summary.flags_hex, "0x11008000");
+        assert_eq!(summary.mask, "Created; Modified;");
+
+        // a folder flag wins over the file one, and item types stay out of the mask
+        let flags = (EventFlag::FSE_IS_DIR | EventFlag::FSE_IS_FILE | EventFlag::FSE_DELETE).bits();
+        assert_eq!(flags::item_type(flags, Version::V2), Some("Folder"));
+        let (entry, _) = Entry::parse(&record(b"a/b", 1, flags, 7), Version::V2).unwrap();
+        assert_eq!(entry.summary().mask, "Removed;");
+        assert_eq!(
+            flags::item_type(EventFlag::FSE_DELETE.bits(), Version::V2),
+            None
+        );
+    }
+
+    #[test]
+    fn archive_keeps_pages_around_errors() {
+        let mut bad = file("a/one", 1);
+        bad.extend_from_slice(b"a/two");
+        let mut stream = [page(&bad), page(&file("a/three", 3))].concat();
+        stream.extend_from_slice(b"garbage");
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&stream).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        let archive = Archive::from_bytes(&gzip).unwrap();
+        let entries: Vec<&Entry> = archive
+            .pages
+            .iter()
+            .flat_map(|p| p.entries.iter())
+            .collect();
+        assert_eq!(
+            entries.iter().map(|e| e.event_id).collect::<Vec<_>>(),
+            [1, 3]
+        );
+        let kinds: Vec<&str> = archive.errors.iter().map(|e| e.kind()).collect();
+        assert_eq!(kinds, ["unterminated_path", "no_page_found"]);
+        assert!(Page::new(&stream).is_err());
+    }
+} // mod tests
@@ -0,0 +1,250 @@
+//! Per-file lifecycle built from the entries of all archives.
+//!
+//! Entries are grouped by node id, or by path when the record has none
+//! (1SLD pages). Only a summary is kept per file, not its entries.
+
+use std::collections::HashMap;
+
+use crate::fsevents::{Entry, EventFlag};
+use crate::renames::Rename;
+
+/// A move of the file to `new_path`.
+#[derive(Debug, Clone)]
+pub struct RenameStep {
+    pub event_id: u64,
+    pub old_path: String,
+    pub new_path: String,
+}
+
+/// Lifecycle of one file: first seen, created, modified N times, renamed,
+/// deleted. Event id lists are in ascending order.
+#[derive(Debug, Clone)]
+pub struct FileHistory {
+    pub node_id: Option<u64>,
+    pub path: String,       // path at the last event
+    pub paths: Vec<String>, // every path the file was seen under
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub event_count: usize,
+    pub created: Vec<u64>,
+    pub modified_count: usize,
+    pub last_modified: Option<u64>,
+    pub renames: Vec<RenameStep>,
+    pub deleted: Vec<u64>,
+}
+
+impl FileHistory {
+    fn new(node_id: Option<u64>, path: &str, event_id: u64) -> Self {
+        FileHistory {
+            node_id,
+            path: String::from(path),
+            paths: vec![],
+            first_seen: event_id,
+            last_seen: event_id,
+            event_count: 0,
+            created: vec![],
+            modified_count: 0,
+            last_modified: None,
+            renames: vec![],
+            deleted: vec![],
+        }
+    }
+
+    /// The last event of the file removed it.
+    pub fn deleted_at_end(&self) -> bool {
+        self.deleted.last() == Some(&self.last_seen)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FileKey {
+    Node(u64),
+    Path(String),
+}
+
+fn file_key(node_id: Option<u64>, path: &str) -> FileKey {
+    match node_id {
+        Some(node_id) if node_id != 0 => FileKey::Node(node_id),
+        _ => FileKey::Path(String::from(path)),
+    }
+}
+
+/// Collects the history of every file seen.
+#[derive(Debug, Default)]
+pub struct HistoryBuilder {
+    files: HashMap<FileKey, FileHistory>,
+}
+
+impl HistoryBuilder {
+    pub fn new() -> Self {
+        HistoryBuilder::default()
+    }
+
+    /// Adds an entry to the history of its file; entries may come in any
+    /// order.
+    pub fn observe(&mut self, entry: &Entry) {
+        let id = entry.event_id;
+        let history = self
+            .files
+            .entry(file_key(entry.node_id, &entry.full_path))
+            .or_insert_with(|| FileHistory::new(entry.node_id, &entry.full_path, id));
+
+        history.event_count += 1;
+        history.first_seen = history.first_seen.min(id);
+        if id >= history.last_seen {
+            history.last_seen = id;
+            history.path = entry.full_path.clone();
+        }
+        if !history.paths.contains(&entry.full_path) {
+            history.paths.push(entry.full_path.clone());
+        }
+
+        let types = entry.event_types();
+        if types.contains(&"Created") || types.contains(&"FolderCreated") {
+            insert_sorted(&mut history.created, id);
+        }
+        if types.contains(&"Modified") {
+            history.modified_count += 1;
+            history.last_modified = history.last_modified.max(Some(id));
+        }
+        if entry.flags.contains(EventFlag::FSE_DELETE) {
+            insert_sorted(&mut history.deleted, id);
+        }
+    }
+
+    /// Records a rename paired from two rename records, in the history of
+    /// the old path, or of the new path when the old path record was not
+    /// observed (a 1SLD record filtered out). A rename of a file not observed
+    /// at all starts a history of its own.
+    pub fn add_rename(&mut self, rename: &Rename) {
+        let old_key = file_key(rename.node_id, &rename.old_path);
+        let new_key = file_key(rename.node_id, &rename.new_path);
+        let key = match self.files.contains_key(&old_key) {
+            true => old_key,
+            false => new_key,
+        };
+        let history = self.files.entry(key).or_insert_with(|| {
+            let mut history = FileHistory::new(rename.node_id, &rename.new_path, rename.event_id);
+            history.last_seen = rename.new_event_id.max(rename.event_id);
+            history.paths = vec![rename.old_path.clone(), rename.new_path.clone()];
+            history
+        });
+
+        let at = history
+            .renames
+            .partition_point(|r| r.event_id <= rename.event_id);
+        history.renames.insert(
+            at,
+            RenameStep {
+                event_id: rename.event_id,
+                old_path: rename.old_path.clone(),
+                new_path: rename.new_path.clone(),
+            },
+        );
+    }
+
+    /// Histories of all files, in the order they were first seen.
+    pub fn finish(self) -> Vec<FileHistory> {
+        let mut files: Vec<FileHistory> = self.files.into_values().collect();
+        files.sort_by_key(|f| f.first_seen);
+        files
+    }
+} // impl HistoryBuilder
+
+fn insert_sorted(ids: &mut Vec<u64>, id: u64) {
+    if let Err(at) = ids.binary_search(&id) {
+        ids.insert(at, id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, event_id: u64, flags: EventFlag, node_id: Option<u64>) -> Entry {
+        Entry {
+            node_id,
+            ..Entry::for_test(path, event_id, flags)
+        }
+    }
+
+    fn rename(old_path: &str, new_path: &str, event_id: u64, node_id: Option<u64>) -> Rename {
+        Rename {
+            old_path: String::from(old_path),
+            new_path: String::from(new_path),
+            event_id,
+            new_event_id: event_id + 1,
+            node_id,
+            source: String::new(),
+        }
+    }
+
+    #[test]
+    fn lifecycle_by_node_id() {
+        let file = EventFlag::FSE_IS_FILE;
+        let mut builder = HistoryBuilder::new();
+        // out of order, under two paths
+        builder.observe(&entry(
+            "a/y.txt",
+            30,
+            file | EventFlag::FSE_CONTENT_MODIFIED,
+            Some(7),
+        ));
+        builder.observe(&entry(
+            "a/x.txt",
+            10,
+            file | EventFlag::FSE_CREATE_FILE,
+            Some(7),
+        ));
+        builder.observe(&entry(
+            "a/x.txt",
+            20,
+            file | EventFlag::FSE_CONTENT_MODIFIED,
+            Some(7),
+        ));
+        builder.observe(&entry("a/y.txt", 40, file | EventFlag::FSE_DELETE, Some(7)));
+        builder.observe(&entry("a/z.txt", 5, file | EventFlag::FSE_DELETE, Some(8)));
+        builder.observe(&entry(
+            "a/z.txt",
+            6,
+            file | EventFlag::FSE_CREATE_FILE,
+            Some(8),
+        ));
+        builder.add_rename(&rename("a/x.txt", "a/y.txt", 25, Some(7)));
+
+        let histories = builder.finish();
+        assert_eq!(histories.len(), 2);
+        let z = &histories[0];
+        assert_eq!((z.first_seen, z.deleted.as_slice()), (5, &[5][..]));
+        assert!(!z.deleted_at_end());
+
+        let x = &histories[1];
+        assert_eq!((x.first_seen, x.last_seen, x.event_count), (10, 40, 4));
+        assert_eq!(x.path, "a/y.txt");
+        assert_eq!(x.paths, ["a/y.txt", "a/x.txt"]);
+        assert_eq!(x.created, [10]);
+        assert_eq!((x.modified_count, x.last_modified), (2, Some(30)));
+        assert_eq!(x.renames.len(), 1);
+        assert_eq!(x.renames[0].new_path, "a/y.txt");
+        assert!(x.deleted_at_end());
+    }
+
+    #[test]
+    fn renames_without_an_old_path_history() {
+        let renamed = EventFlag::FSE_RENAME | EventFlag::FSE_IS_FILE;
+        let mut builder = HistoryBuilder::new();
+        // 1SLD, the old path record was filtered out
+        builder.observe(&entry("a/y.txt", 11, renamed, None));
+        builder.add_rename(&rename("tmp/x.txt", "a/y.txt", 10, None));
+        // neither record was observed
+        builder.add_rename(&rename("tmp/v.txt", "a/w.txt", 20, None));
+
+        let histories = builder.finish();
+        assert_eq!(histories.len(), 2);
+        assert_eq!(histories[0].path, "a/y.txt");
+        assert_eq!(histories[0].renames[0].old_path, "tmp/x.txt");
+        assert_eq!(histories[1].paths, ["tmp/v.txt", "a/w.txt"]);
+        assert_eq!((histories[1].first_seen, histories[1].last_seen), (20, 21));
+        assert_eq!(histories[1].renames.len(), 1);
+    }
+} // mod tests
@@ -0,0 +1,46 @@
+//! Parser for the macOS fseventsd disk log.
+//!
+//! The `.fseventsd` directory of a volume holds gzip compressed archives named
+//! after the last event id they contain. Each archive is a sequence of pages
+//! (`1SLD`, `2SLD` or `3SLD`), each page a sequence of records.
+//!
+//! ```no_run
+//! use fsevents_parser_rs::{find_archives, Archive};
+//!
+//! for path in find_archives("/System/Volumes/Data/.fseventsd") {
+//!     let archive = match Archive::new(&path) {
+//!         Ok(archive) => archive,
+//!         Err(e) => {
+//!             println!("failed to parse: {}", e);
+//!             continue;
+//!         }
+//!     };
+//!     for e in &archive.errors {
+//!         println!("skipped part of {}: {}", path, e);
+//!     }
+//!     for entry in archive.pages.iter().flat_map(|p| p.entries.iter()) {
+//!         println!("{} {} {:?}", entry.event_id, entry.full_path, entry.flags);
+//!     }
+//! }
+//! ```
+
+pub mod carve;
+pub mod filter;
+pub mod flags;
+pub mod fsevents;
+pub mod gaps;
+pub mod history;
+pub mod listing;
+pub mod registry;
+pub mod renames;
+pub mod sort;
+pub mod timeline;
+pub mod tree;
+
+pub use fsevents::{
+    find_archives, name_id_ranges, parse_archive, parse_archive_name, read_volume_uuid, Archive,
+    ArchiveInfo, Diagnostic, Entry, EntryIterator, EventFlag, Page, PageHeader, ParseError,
+    Version,
+};
+pub use registry::Registry;
+pub use timeline::{Anchor, TimeEstimator, TimeWindow};
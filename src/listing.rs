@@ -0,0 +1,299 @@
+//! File system timestamp listings used to anchor event ids to times.
+//!
+//! A listing is a bodyfile (TSK 3.x, `MD5|name|inode|mode|UID|GID|size|atime|
+//! mtime|ctime|crtime`) or a CSV with a header naming `path`, `inode`, `crtime`
+//! and `mtime` columns, taken from the same image as the fseventsd log. The
+//! creation event of a listed file happened at its crtime, and its last
+//! content modification event at its mtime.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::time::SystemTime;
+
+use crate::fsevents::Entry;
+use crate::timeline::{parse_time, Anchor};
+
+/// A file of the listing, with the events matched to it.
+#[derive(Debug, Clone)]
+pub struct ListedFile {
+    pub path: String,
+    pub inode: Option<u64>,
+    pub crtime: Option<SystemTime>,
+    pub mtime: Option<SystemTime>,
+
+    pub created_event: Option<u64>,  // last creation event id seen
+    pub modified_event: Option<u64>, // last content modification event id seen
+}
+
+/// Files of a timestamp listing, indexed by path and inode.
+#[derive(Debug, Default)]
+pub struct TimestampListing {
+    pub files: Vec<ListedFile>,
+
+    by_path: HashMap<String, usize>,
+    by_inode: HashMap<u64, usize>,
+}
+
+impl TimestampListing {
+    /// Reads a bodyfile, or a CSV when the first line has no `|`.
+    pub fn from_path(path: &str) -> Result<Self, std::io::Error> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        let mut first = String::new();
+        reader.read_line(&mut first)?;
+
+        match first.contains('|') {
+            true => Self::from_bodyfile(path),
+            false => Self::from_csv(path),
+        }
+    }
+
+    /// Reads a TSK 3.x bodyfile; lines that do not parse are skipped.
+    pub fn from_bodyfile(path: &str) -> Result<Self, std::io::Error> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let mut listing = TimestampListing::default();
+
+        for line in reader.lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 11 {
+                continue;
+            }
+            // the name may itself contain '|', the last 9 fields are fixed
+            let name = fields[1..fields.len() - 9].join("|");
+            let name = match name.split_once(" -> ") {
+                Some((name, _)) => String::from(name), // symlink target
+                None => name,
+            };
+            let inode = fields[fields.len() - 9]
+                .split('-')
+                .next()
+                .and_then(|i| i.parse().ok());
+
+            listing.add(ListedFile {
+                path: name,
+                inode,
+                crtime: listing_time(fields[fields.len() - 1]),
+                mtime: listing_time(fields[fields.len() - 3]),
+                created_event: None,
+                modified_event: None,
+            });
+        }
+
+        Ok(listing)
+    }
+
+    /// Reads a CSV with a header; the path column may be named `path`,
+    /// `name` or `fullpath`, the inode column `inode` or `node_id`.
+    pub fn from_csv(path: &str) -> Result<Self, std::io::Error> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let column = |names: &[&str]| {
+            headers
+                .iter()
+                .position(|h| names.contains(&h.trim().to_lowercase().as_str()))
+        };
+        let path_column = column(&["path", "name", "fullpath"]);
+        let inode_column = column(&["inode", "node_id"]);
+        let crtime_column = column(&["crtime", "created"]);
+        let mtime_column = column(&["mtime", "modified"]);
+
+        let mut listing = TimestampListing::default();
+        for record in reader.records() {
+            let record = record?;
+            let field = |column: Option<usize>| column.and_then(|c| record.get(c));
+            listing.add(ListedFile {
+                path: String::from(field(path_column).unwrap_or("")),
+                inode: field(inode_column).and_then(|i| i.trim().parse().ok()),
+                crtime: field(crtime_column).and_then(listing_time),
+                mtime: field(mtime_column).and_then(listing_time),
+                created_event: None,
+                modified_event: None,
+            });
+        }
+
+        Ok(listing)
+    }
+
+    fn add(&mut self, file: ListedFile) {
+        let index = self.files.len();
+        if !file.path.is_empty() {
+            self.by_path.insert(normalize_path(&file.path), index);
+        }
+        if let Some(inode) = file.inode {
+            self.by_inode.insert(inode, index);
+        }
+        self.files.push(file);
+    }
+
+    /// Matches `entry` to a listed file, by node id when both sides have
+    /// one, by path otherwise, and records its creation or modification. When
+    /// both sides have a path and a node id, both have to match.
+    pub fn observe(&mut self, entry: &Entry) {
+        let path = normalize_path(&entry.full_path);
+        let by_inode = entry.node_id.and_then(|n| self.by_inode.get(&n));
+        let index = match by_inode.or_else(|| self.by_path.get(path.as_str())) {
+            Some(index) => *index,
+            None => return,
+        };
+
+        // an inode may be reused by another file, a path by another inode
+        let file = &self.files[index];
+        let path_matches =
+            file.path.is_empty() || path.is_empty() || normalize_path(&file.path) == path;
+        let inode_matches = match (entry.node_id, file.inode) {
+            (Some(node_id), Some(inode)) => node_id == inode,
+            _ => true,
+        };
+        if !path_matches || !inode_matches {
+            return;
+        }
+
+        let types = entry.event_types();
+        let file = &mut self.files[index];
+        if types.contains(&"Created") || types.contains(&"FolderCreated") {
+            file.created_event = file.created_event.max(Some(entry.event_id));
+        }
+        if types.contains(&"Modified") {
+            file.modified_event = file.modified_event.max(Some(entry.event_id));
+        }
+    }
+
+    /// Exact anchors from the events matched so far: creation events at the
+    /// crtime, last modification events at the mtime.
+    pub fn anchors(&self) -> Vec<Anchor> {
+        let mut anchors = vec![];
+        for file in &self.files {
+            if let (Some(event_id), Some(time)) = (file.created_event, file.crtime) {
+                anchors.push(Anchor {
+                    event_id,
+                    time,
+                    exact: true,
+                    source: format!("crtime of {}", file.path),
+                });
+            }
+            if let (Some(event_id), Some(time)) = (file.modified_event, file.mtime) {
+                anchors.push(Anchor {
+                    event_id,
+                    time,
+                    exact: true,
+                    source: format!("mtime of {}", file.path),
+                });
+            }
+        }
+        anchors
+    }
+} // impl TimestampListing
+
+// fsevents paths are relative to the volume root
+fn normalize_path(path: &str) -> String {
+    String::from(path.trim_start_matches('/'))
+}
+
+// unix seconds, possibly fractional, or a date; zero means unknown
+fn listing_time(s: &str) -> Option<SystemTime> {
+    let s = s.trim();
+    let s = match s.split_once('.') {
+        Some((secs, fraction)) if fraction.chars().all(|c| c.is_ascii_digit()) => secs,
+        _ => s,
+    };
+    match s {
+        "" | "0" => None,
+        s => parse_time(s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsevents::EventFlag;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn at(secs: u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    // write `content` to a temporary file and read it as a listing
+    fn listing(name: &str, content: &str) -> TimestampListing {
+        let path = std::env::temp_dir().join(format!(
+            "fsevents_parser_rs-listing-{}-{}",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, content).unwrap();
+        let listing = TimestampListing::from_path(path.to_str().unwrap()).unwrap();
+        _ = fs::remove_file(&path);
+        listing
+    }
+
+    fn created(path: &str, event_id: u64, node_id: u64) -> Entry {
+        Entry {
+            node_id: Some(node_id),
+            ..Entry::for_test(
+                path,
+                event_id,
+                EventFlag::FSE_CREATE_FILE | EventFlag::FSE_IS_FILE,
+            )
+        }
+    }
+
+    #[test]
+    fn bodyfile_fields() {
+        let listing = listing(
+            "body",
+            "0|/Users/a/x.txt|12-128-4|r/rrw-r--r--|501|20|5|100|200|300|400\n\
+             0|/Users/a/b|c.txt|13|r/rrw-r--r--|501|20|5|100|200.5|300|0\n\
+             0|/Users/a/l -> /tmp|14|l/lrwxr-xr-x|501|20|5|100|200|300|400\n\
+             0|too|few|fields\n",
+        );
+        assert_eq!(listing.files.len(), 3);
+        let x = &listing.files[0];
+        assert_eq!((x.path.as_str(), x.inode), ("/Users/a/x.txt", Some(12)));
+        assert_eq!((x.mtime, x.crtime), (at(200), at(400)));
+        // a name with a '|' in it, a fractional mtime and an unknown crtime
+        let c = &listing.files[1];
+        assert_eq!((c.path.as_str(), c.inode), ("/Users/a/b|c.txt", Some(13)));
+        assert_eq!((c.mtime, c.crtime), (at(200), None));
+        assert_eq!(listing.files[2].path, "/Users/a/l");
+    }
+
+    #[test]
+    fn csv_header_aliases() {
+        let listing = listing(
+            "csv",
+            "FullPath,Node_ID,Created,Modified\nUsers/a/x.txt,12,100,2022-09-25 08:15:03\n",
+        );
+        let x = &listing.files[0];
+        assert_eq!((x.path.as_str(), x.inode), ("Users/a/x.txt", Some(12)));
+        assert_eq!(x.crtime, at(100));
+        assert_eq!(x.mtime, parse_time("2022-09-25 08:15:03"));
+    }
+
+    #[test]
+    fn listing_times() {
+        assert_eq!(listing_time(" 1664093703 "), at(1664093703));
+        assert_eq!(listing_time("1664093703.25"), at(1664093703));
+        assert_eq!(listing_time("0"), None);
+        assert_eq!(listing_time(""), None);
+        assert_eq!(listing_time("2022-09-25"), parse_time("2022-09-25"));
+    }
+
+    #[test]
+    fn observe_requires_path_and_inode_to_match() {
+        let mut listing = listing(
+            "observe",
+            "path,inode,crtime,mtime\n/Users/a/x.txt,12,100,200\nUsers/a/y.txt,,100,200\n",
+        );
+        // inode reused by another file, path reused by another inode
+        listing.observe(&created("Users/a/other.txt", 1, 12));
+        listing.observe(&created("Users/a/x.txt", 2, 99));
+        assert_eq!(listing.files[0].created_event, None);
+
+        listing.observe(&created("Users/a/x.txt", 3, 12));
+        assert_eq!(listing.files[0].created_event, Some(3));
+        // no inode listed, the path alone matches
+        listing.observe(&created("Users/a/y.txt", 4, 99));
+        assert_eq!(listing.files[1].created_event, Some(4));
+        assert_eq!(listing.anchors().len(), 2);
+    }
+} // mod tests
@@ -1,9 +1,20 @@
 mod args;
 use args::*;
 
-mod fsevents;
-mod registry;
-use registry::Registry;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufWriter, Read, Write};
+
+use fsevents_parser_rs::carve;
+use fsevents_parser_rs::filter::{EntryFilter, PathFilter};
+use fsevents_parser_rs::fsevents::{self, Archive, ArchiveInfo, Diagnostic, EntryIterator};
+use fsevents_parser_rs::gaps::{Discontinuity, GapTracker};
+use fsevents_parser_rs::history::HistoryBuilder;
+use fsevents_parser_rs::listing::TimestampListing;
+use fsevents_parser_rs::registry::{self, Registry};
+use fsevents_parser_rs::renames::RenameTracker;
+use fsevents_parser_rs::timeline::{self, TimeEstimator};
+use fsevents_parser_rs::tree::PathTree;
 
 fn main() {
     // get args
@@ -12,9 +23,19 @@ fn main() {
         return;
     }
 
+    let filter = match build_filter(&args) {
+        Some(filter) => filter,
+        None => return,
+    };
+
+    if args.carve {
+        carve_and_export(&args, filter);
+        return;
+    }
+
     // find all archives in fseventsd directory
     let archive_files = fsevents::find_archives(&args.input_path);
-    if archive_files.len() == 0 {
+    if archive_files.is_empty() {
         println!("no valid archive found in input directory, existing");
     }
     println!(
@@ -22,53 +43,425 @@ fn main() {
         archive_files.len(),
         args.input_path
     );
+    match fsevents::read_volume_uuid(&args.input_path) {
+        Some(uuid) => println!("volume uuid: {}", uuid),
+        None => println!("no fseventsd-uuid found in {}", args.input_path),
+    }
 
-    parse_and_export(&archive_files, &args.output_path, args.format);
+    parse_and_export(&archive_files, &args, filter);
 }
 
-fn parse_and_export(archive_files: &Vec<String>, output_path: &str, format: ArgsOutputFormat) {
-    // create registry
-    let mut reg: Box<dyn Registry>;
-    match format {
-        ArgsOutputFormat::Json => match registry::json::JsonRegistry::new(output_path) {
-            Ok(r) => {
-                reg = r;
+fn parse_and_export(archive_files: &[String], args: &ArgParse, filter: EntryFilter) {
+    let mut reg = match create_registry(args) {
+        Some(r) => r,
+        None => return,
+    };
+    let skipped = skipped_archives(archive_files, &filter);
+    if !skipped.is_empty() {
+        println!(
+            "skipping {} archives outside the event id and time window",
+            skipped.len()
+        );
+    }
+    let mut pipeline = Pipeline {
+        estimator: match args.estimate_times || !args.anchor.is_empty() || args.timestamps.is_some()
+        {
+            true => Some(build_estimator(archive_files, args, &skipped)),
+            false => None,
+        },
+        renames: RenameTracker::new(),
+        history: match args.history {
+            true => Some(HistoryBuilder::new()),
+            false => None,
+        },
+        tree: args.tree.as_ref().map(|_| PathTree::new()),
+        filter,
+        filtered_count: 0,
+        gaps: Some(GapTracker::new()),
+    };
+
+    // parse fsevents and save
+    archive_files.iter().for_each(|f| {
+        if skipped.contains(f) {
+            if let (Some(gaps), Ok(info)) = (pipeline.gaps.as_mut(), ArchiveInfo::from_path(f)) {
+                gaps.observe_unread(&info.filename, info.mtime);
             }
+            return;
+        }
+        let (info, fd) = match open_archive(f) {
+            Ok(opened) => opened,
             Err(e) => {
-                println!("failed to create registry: {}", e);
+                println!("failed to open archive: {}", e);
+                reg.export_diagnostics(&[Diagnostic {
+                    archive: String::from(f),
+                    page: None,
+                    offset: 0,
+                    kind: "io",
+                    message: e.to_string(),
+                    bytes_skipped: 0,
+                }]);
                 return;
             }
+        };
+
+        let mut entries = Archive::entries(fd)
+            .with_archive_name(&info.filename)
+            .with_recovery(args.recover);
+        export_stream(reg.as_mut(), &info, &mut entries, &mut pipeline);
+    });
+    finish(reg.as_mut(), pipeline, args);
+    println!(
+        "{} records written to {}",
+        reg.written_count(),
+        args.output_path
+    );
+}
+
+fn carve_and_export(args: &ArgParse, filter: EntryFilter) {
+    let mut reg = match create_registry(args) {
+        Some(r) => r,
+        None => return,
+    };
+    let mut pipeline = Pipeline {
+        // carved archives have no mtime, only the user anchors apply
+        estimator: match args.anchor.is_empty() {
+            true => None,
+            false => Some(build_estimator(&[], args, &HashSet::new())),
         },
-        ArgsOutputFormat::Csv => match registry::csv::CsvRegistry::new(output_path) {
-            Ok(r) => {
-                reg = r;
+        renames: RenameTracker::new(),
+        history: match args.history {
+            true => Some(HistoryBuilder::new()),
+            false => None,
+        },
+        tree: args.tree.as_ref().map(|_| PathTree::new()),
+        filter,
+        filtered_count: 0,
+        // carved archives are not in event id order
+        gaps: None,
+    };
+
+    match carve::carve(&args.input_path, args.recover, |info, entries| {
+        export_stream(reg.as_mut(), info, entries, &mut pipeline)
+    }) {
+        Ok(count) => println!("carved {} archives from {}", count, args.input_path),
+        Err(e) => println!("failed to carve input: {}", e),
+    }
+    finish(reg.as_mut(), pipeline, args);
+    println!(
+        "{} records written to {}",
+        reg.written_count(),
+        args.output_path
+    );
+}
+
+fn build_filter(args: &ArgParse) -> Option<EntryFilter> {
+    let paths = args
+        .include
+        .iter()
+        .try_fold(PathFilter::new(), |f, p| f.include(p))
+        .and_then(|f| args.exclude.iter().try_fold(f, |f, p| f.exclude(p)));
+    let paths = match paths {
+        Ok(paths) => paths,
+        Err(e) => {
+            println!("invalid path pattern: {}", e);
+            return None;
+        }
+    };
+
+    let filter = EntryFilter::new()
+        .with_paths(paths)
+        .with_event_ids(args.min_event_id, args.max_event_id)
+        .with_times(args.since, args.until);
+    match &args.flags {
+        Some(flags) => Some(filter.with_flags(flags.clone())),
+        None => Some(filter),
+    }
+}
+
+fn create_registry(args: &ArgParse) -> Option<Box<dyn Registry>> {
+    let output_path = args.output_path.as_str();
+
+    let reg: Option<Box<dyn Registry>> = match args.format {
+        ArgsOutputFormat::Json => match registry::json::JsonRegistry::new(output_path) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                println!("failed to create registry: {}", e);
+                None
             }
+        },
+        ArgsOutputFormat::Csv => match registry::csv::CsvRegistry::new(output_path) {
+            Ok(r) => Some(r),
             Err(e) => {
                 println!("failed to create csv registry: {}", e);
-                return;
+                None
             }
         },
         ArgsOutputFormat::Sqlite => match registry::sqlite::SqliteRegistry::new(output_path) {
-            Ok(r) => {
-                reg = r;
-            }
+            Ok(r) => Some(r),
             Err(e) => {
                 println!("failed to create sqlite registry: {}", e);
-                return;
+                None
             }
         },
+        ArgsOutputFormat::Fseventsparser => {
+            match registry::fseventsparser::FseventsParserRegistry::new(output_path) {
+                Ok(r) => Some(r),
+                Err(e) => {
+                    println!("failed to create fseventsparser registry: {}", e);
+                    None
+                }
+            }
+        }
+    };
+
+    let reg = reg?;
+    match args.sort {
+        true => Some(registry::sorted::SortedRegistry::new(
+            reg,
+            args.sort_run_size,
+        )),
+        false => Some(reg),
     }
+}
 
-    // parse fsevents and save
-    archive_files.iter().for_each(|f| {
-        if let Some(archive) = fsevents::parse_archive(f) {
-            println!("---------- {} ----------", archive.filename);
-            println!("page count: {}", archive.pages.len());
-            archive.pages.iter().for_each(|p| {
-                println!("entry count: {}", p.entries.len());
-            });
-
-            reg.export_archive(&archive);
-        }
+// archives whose file name and mtime tell that no entry in them can pass the
+// filter
+fn skipped_archives(archive_files: &[String], filter: &EntryFilter) -> HashSet<String> {
+    let (files, infos): (Vec<&String>, Vec<ArchiveInfo>) = archive_files
+        .iter()
+        .filter_map(|f| Some((f, ArchiveInfo::from_path(f).ok()?)))
+        .unzip();
+    files
+        .into_iter()
+        .zip(filter.skippable(&infos))
+        .filter(|(_, skippable)| *skippable)
+        .map(|(f, _)| f.clone())
+        .collect()
+}
+
+// anchor the archives and the events of files in the timestamp listing and
+// print the anchors and uncertainty of every archive
+fn build_estimator(
+    archive_files: &[String],
+    args: &ArgParse,
+    skipped: &HashSet<String>,
+) -> TimeEstimator {
+    let mut listing = match &args.timestamps {
+        Some(path) => match TimestampListing::from_path(path) {
+            Ok(listing) => {
+                println!("{} files in timestamp listing", listing.files.len());
+                Some(listing)
+            }
+            Err(e) => {
+                println!("failed to read timestamp listing: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let (mut estimator, mut spans) = TimeEstimator::from_archives(
+        archive_files,
+        args.recover,
+        |f| !skipped.contains(f),
+        |e| {
+            if let Some(listing) = listing.as_mut() {
+                listing.observe(e);
+            }
+        },
+    );
+    args.anchor
+        .iter()
+        .for_each(|a| estimator.add_anchor(a.clone()));
+
+    if let Some(listing) = listing {
+        let anchors = listing.anchors();
+        let matched = anchors.len();
+        let rejected = estimator.fit_anchors(anchors);
+        println!(
+            "{} anchors matched in timestamp listing, {} rejected as inconsistent",
+            matched,
+            rejected.len()
+        );
+        rejected
+            .iter()
+            .for_each(|a| println!("rejected anchor: {} at event {}", a.source, a.event_id));
+    }
+    println!("{} time anchors", estimator.anchors().count());
+
+    // fitted anchors and uncertainty per archive
+    spans.sort_by_key(|s| (s.mtime, s.first));
+    spans.iter().for_each(|span| {
+        let anchors = estimator
+            .epoch_anchors(span.mtime)
+            .iter()
+            .filter(|a| span.first <= a.event_id && a.event_id <= span.last)
+            .collect::<Vec<_>>();
+        let uncertainty = match estimator.uncertainty(span.first, span.last, span.mtime) {
+            Some(width) => format!("{}s", width.as_secs()),
+            None => String::from("unbounded"),
+        };
+        println!(
+            "{}: events {}-{}, {} anchors, uncertainty {}",
+            span.filename,
+            span.first,
+            span.last,
+            anchors.len(),
+            uncertainty
+        );
+        anchors.iter().for_each(|a| {
+            println!(
+                "    anchor: event {} at {} ({})",
+                a.event_id,
+                timeline::format_time(a.time),
+                a.source
+            )
+        });
     });
+
+    estimator
+}
+
+// what entries go through between parsing and the registry
+struct Pipeline {
+    estimator: Option<TimeEstimator>,
+    renames: RenameTracker,
+    history: Option<HistoryBuilder>,
+    tree: Option<PathTree>,
+    filter: EntryFilter,
+    filtered_count: usize, // entries left out by the filter
+    gaps: Option<GapTracker>,
+}
+
+// export the entries of one archive and what could not be parsed in it
+fn export_stream<R: Read>(
+    reg: &mut dyn Registry,
+    info: &ArchiveInfo,
+    entries: &mut EntryIterator<R>,
+    pipeline: &mut Pipeline,
+) {
+    println!("---------- {} ----------", info.filename);
+    let estimator = pipeline.estimator.as_ref();
+    let renames = &mut pipeline.renames;
+    let mut history = pipeline.history.as_mut();
+    let mut tree = pipeline.tree.as_mut();
+    let filter = &pipeline.filter;
+    let mut filtered_count = 0;
+    reg.export_entries(
+        info,
+        &mut entries
+            .by_ref()
+            .filter_map(|r| r.ok())
+            .map(|mut e| {
+                if let Some(estimator) = estimator {
+                    estimator.apply(&mut e, info.mtime);
+                }
+                e
+            })
+            .filter(|e| {
+                // renames are paired across the filter, either side may pass
+                let accepted = filter.accepts(e);
+                renames.observe(e, &info.filename, accepted);
+                filtered_count += usize::from(!accepted);
+                accepted
+            })
+            .inspect(|e| {
+                if let Some(history) = history.as_mut() {
+                    history.observe(e);
+                }
+                if let Some(tree) = tree.as_mut() {
+                    tree.observe(e);
+                }
+            }),
+    );
+    let paired = pipeline.renames.take();
+    if let Some(history) = pipeline.history.as_mut() {
+        paired.iter().for_each(|r| history.add_rename(r));
+    }
+    if let Some(tree) = pipeline.tree.as_mut() {
+        paired.iter().for_each(|r| tree.add_rename(r));
+    }
+    reg.export_renames(&paired);
+    if let Some(mismatch) = entries.max_event_id.and_then(|max| info.id_mismatch(max)) {
+        entries.diagnostics.push(mismatch);
+    }
+    if let (Some(gaps), Some(first), Some(last)) = (
+        pipeline.gaps.as_mut(),
+        entries.min_event_id,
+        entries.max_event_id,
+    ) {
+        gaps.observe(&info.filename, info.mtime, first, last);
+    }
+    entries
+        .diagnostics
+        .iter()
+        .for_each(|d| println!("{}", d.message));
+    reg.export_diagnostics(&entries.diagnostics);
+    println!("page count: {}", entries.page_count);
+    println!("entry count: {}", entries.entry_count);
+    if !pipeline.filter.is_empty() {
+        println!("filtered out: {}", filtered_count);
+        pipeline.filtered_count += filtered_count;
+    }
+    if entries.unknown_flags_count > 0 {
+        println!(
+            "warning: {} entries with undefined flag bits {:#010x}",
+            entries.unknown_flags_count, entries.unknown_flags
+        );
+    }
+}
+
+// write out what was held back until every archive was read
+fn finish(reg: &mut dyn Registry, pipeline: Pipeline, args: &ArgParse) {
+    if let Some(history) = pipeline.history {
+        let histories = history.finish();
+        println!("{} files in history", histories.len());
+        reg.export_history(&histories);
+    }
+    if let (Some(tree), Some(path)) = (pipeline.tree, &args.tree) {
+        match write_tree(&tree, path) {
+            Ok(()) => println!("path tree written to {}", path),
+            Err(e) => println!("failed to write path tree: {}", e),
+        }
+    }
+    if let Some(gaps) = &pipeline.gaps {
+        let found = gaps.discontinuities(args.min_gap);
+        found
+            .iter()
+            .for_each(|d| println!("{}", d.to_diagnostic().message));
+        reg.export_diagnostics(&found.iter().map(|d| d.to_diagnostic()).collect::<Vec<_>>());
+        let gap_sizes = found
+            .iter()
+            .filter(|d| matches!(d, Discontinuity::Gap { .. }))
+            .map(|d| d.size())
+            .collect::<Vec<u64>>();
+        println!(
+            "{} event id gaps of at least {} ids between archives, {} event ids missing, {} rollbacks",
+            gap_sizes.len(),
+            args.min_gap,
+            gap_sizes.iter().sum::<u64>(),
+            found.len() - gap_sizes.len()
+        );
+    }
+    reg.finish();
+    println!(
+        "{} rename records left without a partner",
+        pipeline.renames.unpaired_count()
+    );
+    if !pipeline.filter.is_empty() {
+        println!("{} entries filtered out", pipeline.filtered_count);
+    }
+}
+
+fn write_tree(tree: &PathTree, path: &str) -> Result<(), std::io::Error> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    match path.ends_with(".json") {
+        true => tree.write_json(&mut writer)?,
+        false => tree.write_text(&mut writer)?,
+    }
+    writer.flush()
+}
+
+fn open_archive(path: &str) -> Result<(ArchiveInfo, fs::File), std::io::Error> {
+    Ok((ArchiveInfo::from_path(path)?, fs::File::open(path)?))
 }
@@ -1,6 +1,251 @@
-use crate::fsevents::Archive;
+//! Output backends the parsed entries are exported to.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::fsevents::{Archive, ArchiveInfo, Diagnostic, Entry};
+use crate::history::FileHistory;
+use crate::renames::Rename;
+
+/// An output sink for parsed archives.
 pub trait Registry {
-    fn export_archive(&mut self, archive: &Archive) -> bool;
+    /// Writes `entries` read from `archive`, returning false on fatal failure.
+    fn export_entries(
+        &mut self,
+        archive: &ArchiveInfo,
+        entries: &mut dyn Iterator<Item = Entry>,
+    ) -> bool;
+    /// Writes every entry of a fully parsed archive.
+    fn export_archive(&mut self, archive: &Archive) -> bool {
+        let mut entries = archive.pages.iter().flat_map(|p| p.entries.iter().cloned());
+        self.export_entries(&archive.info, &mut entries)
+    }
+    /// Writes the parts of an archive that could not be parsed.
+    fn export_diagnostics(&mut self, diagnostics: &[Diagnostic]) -> bool;
+    /// Writes renames reconstructed from pairs of rename records.
+    fn export_renames(&mut self, renames: &[Rename]) -> bool;
+    /// Writes the lifecycle of every file.
+    fn export_history(&mut self, histories: &[FileHistory]) -> bool;
+    /// Number of records written so far.
+    fn written_count(&self) -> usize;
+    /// Writes out what is still held back, once every archive was exported.
+    fn finish(&mut self) -> bool {
+        true
+    }
+}
+
+// path of a file written next to the output, e.g. output.parse_errors.json
+fn sidecar_path(path: &str, name: &str) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("output");
+    let file_name = match path.extension().and_then(|s| s.to_str()) {
+        Some(ext) => format!("{}.{}.{}", stem, name, ext),
+        None => format!("{}.{}", stem, name),
+    };
+    String::from(path.with_file_name(file_name).to_str().unwrap_or_default())
+}
+
+const HISTORY_HEADER: [&str; 12] = [
+    "node_id",
+    "path",
+    "paths",
+    "first_seen",
+    "last_seen",
+    "event_count",
+    "created",
+    "modified_count",
+    "last_modified",
+    "renamed_to",
+    "deleted",
+    "deleted_at_end",
+];
+
+// a file history as one flat row, lists joined with "; "
+fn history_row(history: &FileHistory) -> [String; 12] {
+    let join = |ids: &[u64]| {
+        ids.iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<String>>()
+            .join("; ")
+    };
+
+    [
+        history.node_id.map(|n| n.to_string()).unwrap_or_default(),
+        history.path.clone(),
+        history.paths.join("; "),
+        history.first_seen.to_string(),
+        history.last_seen.to_string(),
+        history.event_count.to_string(),
+        join(&history.created),
+        history.modified_count.to_string(),
+        history
+            .last_modified
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        history
+            .renames
+            .iter()
+            .map(|r| r.new_path.as_str())
+            .collect::<Vec<&str>>()
+            .join("; "),
+        join(&history.deleted),
+        history.deleted_at_end().to_string(),
+    ]
+}
+
+/// A parse error as written to the parse errors sidecar.
+#[derive(Serialize)]
+struct DiagnosticRow<'a> {
+    source: &'a str, // source archive file name
+    page: Option<usize>,
+    offset: u64,
+    kind: &'a str,
+    message: &'a str,
+    bytes_skipped: u64,
+}
+
+impl<'a> From<&'a Diagnostic> for DiagnosticRow<'a> {
+    fn from(diagnostic: &'a Diagnostic) -> Self {
+        DiagnosticRow {
+            source: &diagnostic.archive,
+            page: diagnostic.page,
+            offset: diagnostic.offset,
+            kind: diagnostic.kind,
+            message: &diagnostic.message,
+            bytes_skipped: diagnostic.bytes_skipped,
+        }
+    }
+}
+
+/// A rename as written to the renames sidecar.
+#[derive(Serialize)]
+struct RenameRow<'a> {
+    old_path: &'a str,
+    new_path: &'a str,
+    event_id: u64, // event id of the old path record
+    node_id: Option<u64>,
+    source: &'a str, // source archive file name
+}
+
+impl<'a> From<&'a Rename> for RenameRow<'a> {
+    fn from(rename: &'a Rename) -> Self {
+        RenameRow {
+            old_path: &rename.old_path,
+            new_path: &rename.new_path,
+            event_id: rename.event_id,
+            node_id: rename.node_id,
+            source: &rename.source,
+        }
+    }
+}
+
+fn delimited_writer(path: &str, delimiter: u8) -> Result<::csv::Writer<fs::File>, ::csv::Error> {
+    ::csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_path(path)
+}
+
+/// Parse error, rename and file history sidecars of the delimited text
+/// registries, `format` naming both the file extension and the format in
+/// messages.
+struct DelimitedSidecars {
+    path: String,
+    delimiter: u8,
+    format: &'static str,
+    errors_writer: ::csv::Writer<fs::File>,
+    renames_writer: ::csv::Writer<fs::File>,
+    history_writer: Option<::csv::Writer<fs::File>>, // created on first use
+}
+
+impl DelimitedSidecars {
+    fn new(path: &str, delimiter: u8, format: &'static str) -> Result<Self, ::csv::Error> {
+        Ok(DelimitedSidecars {
+            path: String::from(path),
+            delimiter,
+            format,
+            errors_writer: delimited_writer(&sidecar_path(path, "parse_errors"), delimiter)?,
+            renames_writer: delimited_writer(&sidecar_path(path, "renames"), delimiter)?,
+            history_writer: None,
+        })
+    }
+
+    fn export_diagnostics(&mut self, diagnostics: &[Diagnostic]) -> bool {
+        for diagnostic in diagnostics {
+            if let Err(e) = self
+                .errors_writer
+                .serialize(DiagnosticRow::from(diagnostic))
+            {
+                println!("failed to serialize parse error to {}: {}", self.format, e);
+                continue;
+            }
+        }
+        if let Err(e) = self.errors_writer.flush() {
+            println!("failed to write parse errors to file: {}", e);
+            return false;
+        }
+
+        true
+    }
+
+    fn export_renames(&mut self, renames: &[Rename]) -> bool {
+        for rename in renames {
+            if let Err(e) = self.renames_writer.serialize(RenameRow::from(rename)) {
+                println!("failed to serialize rename to {}: {}", self.format, e);
+                continue;
+            }
+        }
+        if let Err(e) = self.renames_writer.flush() {
+            println!("failed to write renames to file: {}", e);
+            return false;
+        }
+
+        true
+    }
+
+    fn export_history(&mut self, histories: &[FileHistory]) -> bool {
+        let writer = match &mut self.history_writer {
+            Some(writer) => writer,
+            None => {
+                let created =
+                    delimited_writer(&sidecar_path(&self.path, "file_history"), self.delimiter)
+                        .and_then(|mut w| w.write_record(HISTORY_HEADER).map(|_| w));
+                match created {
+                    Ok(writer) => self.history_writer.insert(writer),
+                    Err(e) => {
+                        println!("failed to create {} file history: {}", self.format, e);
+                        return false;
+                    }
+                }
+            }
+        };
+
+        for history in histories {
+            if let Err(e) = writer.write_record(history_row(history)) {
+                println!("failed to serialize file history to {}: {}", self.format, e);
+                continue;
+            }
+        }
+        if let Err(e) = writer.flush() {
+            println!("failed to write file history to file: {}", e);
+            return false;
+        }
+
+        true
+    }
+} // impl DelimitedSidecars
+
+// unix seconds of an estimated time bound
+fn bound_secs(time: Option<std::time::SystemTime>) -> Option<u64> {
+    time.map(|t| {
+        t.duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    })
 }
 
 pub mod json {
@@ -12,69 +257,214 @@ pub mod json {
     use serde::Serialize;
     use serde_json;
 
-    use crate::fsevents::Archive;
-    use crate::registry::Registry;
+    use crate::fsevents::{ArchiveInfo, Diagnostic, Entry};
+    use crate::history::FileHistory;
+    use crate::registry::{bound_secs, sidecar_path, DiagnosticRow, Registry, RenameRow};
+    use crate::renames::Rename;
 
+    /// Writes one JSON object per line, parse errors to a
+    /// `<output>.parse_errors.json` sidecar, renames to a
+    /// `<output>.renames.json` sidecar and file histories, nested, to a
+    /// `<output>.file_history.json` sidecar.
     pub struct JsonRegistry {
         pub written_count: usize,
 
+        path: String,
         fd: fs::File,
+        errors_fd: fs::File,
+        renames_fd: fs::File,
+        history_fd: Option<fs::File>, // created on first use
     }
 
     #[derive(Serialize)]
     struct JsonRecord {
-        path: String,  // record path
-        id: u64,       // record id
-        flags: String, // flag description
+        path: String,             // record path
+        id: u64,                  // record id
+        flags: Vec<&'static str>, // names of the flags set
+        flags_raw: u32,
+        event_types: Vec<&'static str>, // normalized event types
+        node_id: Option<u64>,
+        extra_v3: Option<u32>,
+        recovered: bool, // found by best-effort parsing
 
         create_ts: u64,
         modify_ts: u64,
-        source: String, // source archive file name
+        source: String,             // source archive file name
+        source_offset: Option<u64>, // offset in a carved image
+        volume_uuid: Option<String>,
+        time_lower_bound: Option<u64>, // estimated from anchors
+        time_upper_bound: Option<u64>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonFileHistory<'a> {
+        node_id: Option<u64>,
+        path: &'a str, // path at the last event
+        paths: &'a [String],
+        first_seen: u64,
+        last_seen: u64,
+        event_count: usize,
+        created: &'a [u64],
+        modified: JsonModified,
+        renamed: Vec<JsonRenameStep<'a>>,
+        deleted: &'a [u64],
+        deleted_at_end: bool,
+    }
+
+    #[derive(Serialize)]
+    struct JsonModified {
+        count: usize,
+        last: Option<u64>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonRenameStep<'a> {
+        event_id: u64,
+        from: &'a str,
+        to: &'a str,
     }
 
     impl JsonRegistry {
         pub fn new(path: &str) -> Result<Box<JsonRegistry>, std::io::Error> {
             Ok(Box::new(JsonRegistry {
                 written_count: 0,
+                path: String::from(path),
                 fd: fs::File::create(path)?,
+                errors_fd: fs::File::create(sidecar_path(path, "parse_errors"))?,
+                renames_fd: fs::File::create(sidecar_path(path, "renames"))?,
+                history_fd: None,
             }))
         }
     } // impl JsonResgistry
 
     impl Registry for JsonRegistry {
-        fn export_archive(&mut self, archive: &Archive) -> bool {
-            for page in archive.pages.iter() {
-                for entry in page.entries.iter() {
-                    let json_record = JsonRecord {
-                        path: String::from(&entry.full_path),
-                        id: entry.event_id,
-                        flags: format!("{:?}", entry.flags),
-
-                        create_ts: archive
-                            .ctime
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs(),
-                        modify_ts: archive
-                            .mtime
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs(),
-                        source: String::from(&archive.filename),
-                    };
-
-                    if let Ok(j) = serde_json::to_string(&json_record) {
-                        if let Err(e) = self.fd.write_all(&j.as_bytes()) {
-                            println!("failed to write json record: {}", e);
-                            break;
-                        }
-                        _ = self.fd.write(b"\n");
+        fn export_entries(
+            &mut self,
+            archive: &ArchiveInfo,
+            entries: &mut dyn Iterator<Item = Entry>,
+        ) -> bool {
+            for entry in entries {
+                let json_record = JsonRecord {
+                    path: String::from(&entry.full_path),
+                    id: entry.event_id,
+                    flags: entry.flags.names(),
+                    flags_raw: entry.flags_raw,
+                    event_types: entry.event_types(),
+                    node_id: entry.node_id,
+                    extra_v3: entry.extra_v3,
+                    recovered: entry.recovered,
+
+                    create_ts: archive
+                        .ctime
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                    modify_ts: archive
+                        .mtime
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                    source: String::from(&archive.filename),
+                    source_offset: archive.source_offset,
+                    volume_uuid: archive.volume_uuid.clone(),
+                    time_lower_bound: bound_secs(entry.time_lower_bound),
+                    time_upper_bound: bound_secs(entry.time_upper_bound),
+                };
+
+                if let Ok(j) = serde_json::to_string(&json_record) {
+                    if let Err(e) = self.fd.write_all(j.as_bytes()) {
+                        println!("failed to write json record: {}", e);
+                        break;
+                    }
+                    _ = self.fd.write(b"\n");
+                    self.written_count += 1;
+                }
+            }
+
+            true
+        }
+
+        fn export_diagnostics(&mut self, diagnostics: &[Diagnostic]) -> bool {
+            for diagnostic in diagnostics {
+                if let Ok(j) = serde_json::to_string(&DiagnosticRow::from(diagnostic)) {
+                    if let Err(e) = self.errors_fd.write_all(j.as_bytes()) {
+                        println!("failed to write json parse error: {}", e);
+                        return false;
                     }
+                    _ = self.errors_fd.write(b"\n");
                 }
             }
 
             true
         }
+
+        fn export_renames(&mut self, renames: &[Rename]) -> bool {
+            for rename in renames {
+                if let Ok(j) = serde_json::to_string(&RenameRow::from(rename)) {
+                    if let Err(e) = self.renames_fd.write_all(j.as_bytes()) {
+                        println!("failed to write json rename: {}", e);
+                        return false;
+                    }
+                    _ = self.renames_fd.write(b"\n");
+                }
+            }
+
+            true
+        }
+
+        fn export_history(&mut self, histories: &[FileHistory]) -> bool {
+            let fd = match &mut self.history_fd {
+                Some(fd) => fd,
+                None => match fs::File::create(sidecar_path(&self.path, "file_history")) {
+                    Ok(fd) => self.history_fd.insert(fd),
+                    Err(e) => {
+                        println!("failed to create json file history: {}", e);
+                        return false;
+                    }
+                },
+            };
+
+            for history in histories {
+                let json_history = JsonFileHistory {
+                    node_id: history.node_id,
+                    path: &history.path,
+                    paths: &history.paths,
+                    first_seen: history.first_seen,
+                    last_seen: history.last_seen,
+                    event_count: history.event_count,
+                    created: &history.created,
+                    modified: JsonModified {
+                        count: history.modified_count,
+                        last: history.last_modified,
+                    },
+                    renamed: history
+                        .renames
+                        .iter()
+                        .map(|r| JsonRenameStep {
+                            event_id: r.event_id,
+                            from: &r.old_path,
+                            to: &r.new_path,
+                        })
+                        .collect(),
+                    deleted: &history.deleted,
+                    deleted_at_end: history.deleted_at_end(),
+                };
+
+                if let Ok(j) = serde_json::to_string(&json_history) {
+                    if let Err(e) = fd.write_all(j.as_bytes()) {
+                        println!("failed to write json file history: {}", e);
+                        return false;
+                    }
+                    _ = fd.write(b"\n");
+                }
+            }
+
+            true
+        }
+
+        fn written_count(&self) -> usize {
+            self.written_count
+        }
     } // impl Registry for JsonRegistry
 } // mod json_registry
 
@@ -85,58 +475,133 @@ pub mod csv {
 
     use csv;
 
-    use crate::fsevents::Archive;
-    use crate::registry::Registry;
+    use crate::fsevents::{ArchiveInfo, Diagnostic, Entry, EventFlag};
+    use crate::history::FileHistory;
+    use crate::registry::{bound_secs, delimited_writer, DelimitedSidecars, Registry};
+    use crate::renames::Rename;
+
+    const RECORD_HEADER: [&str; 15] = [
+        "path",
+        "id",
+        "flags",
+        "flags_raw",
+        "event_types",
+        "node_id",
+        "extra_v3",
+        "recovered",
+        "create_ts",
+        "modify_ts",
+        "source",
+        "source_offset",
+        "volume_uuid",
+        "time_lower_bound",
+        "time_upper_bound",
+    ];
 
+    /// Writes one CSV row per entry, with a column per flag bit, parse errors to a
+    /// `<output>.parse_errors.csv` sidecar, renames to a
+    /// `<output>.renames.csv` sidecar and file histories to a
+    /// `<output>.file_history.csv` sidecar.
     pub struct CsvRegistry {
         pub written_count: usize,
 
         writer: csv::Writer<fs::File>,
+        sidecars: DelimitedSidecars,
     }
 
     impl CsvRegistry {
         pub fn new(path: &str) -> Result<Box<CsvRegistry>, std::io::Error> {
+            let mut writer = delimited_writer(path, b',')?;
+            writer.write_record(
+                RECORD_HEADER
+                    .iter()
+                    .chain(EventFlag::NAMES.iter().map(|(name, _)| name)),
+            )?;
+
             Ok(Box::new(CsvRegistry {
                 written_count: 0,
-                writer: csv::Writer::from_path(path)?,
+                writer,
+                sidecars: DelimitedSidecars::new(path, b',', "csv")?,
             }))
         }
     } // impl JsonResgistry
 
     impl Registry for CsvRegistry {
-        fn export_archive(&mut self, archive: &Archive) -> bool {
-            for page in archive.pages.iter() {
-                for entry in page.entries.iter() {
-                    let csv_record = (
-                        &entry.full_path,
-                        &entry.event_id,
-                        format!("{:?}", entry.flags),
-                        archive
-                            .ctime
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs(),
-                        archive
-                            .mtime
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs(),
-                        &archive.filename,
-                    );
-
-                    if let Err(e) = self.writer.serialize(csv_record) {
-                        println!("failed to serialize record to csv: {}", e);
-                        continue;
-                    }
-                    if let Err(e) = self.writer.flush() {
-                        println!("failed to write record to file: {}", e);
-                        break;
-                    }
+        fn export_entries(
+            &mut self,
+            archive: &ArchiveInfo,
+            entries: &mut dyn Iterator<Item = Entry>,
+        ) -> bool {
+            for entry in entries {
+                let mut csv_record = vec![
+                    entry.full_path.clone(),
+                    entry.event_id.to_string(),
+                    format!("{:?}", entry.flags),
+                    entry.flags_raw.to_string(),
+                    entry.event_types().join("; "),
+                    entry.node_id.map(|n| n.to_string()).unwrap_or_default(),
+                    entry.extra_v3.map(|n| n.to_string()).unwrap_or_default(),
+                    entry.recovered.to_string(),
+                    archive
+                        .ctime
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs()
+                        .to_string(),
+                    archive
+                        .mtime
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs()
+                        .to_string(),
+                    archive.filename.clone(),
+                    archive
+                        .source_offset
+                        .map(|o| o.to_string())
+                        .unwrap_or_default(),
+                    archive.volume_uuid.clone().unwrap_or_default(),
+                    bound_secs(entry.time_lower_bound)
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                    bound_secs(entry.time_upper_bound)
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                ];
+                csv_record.extend(
+                    EventFlag::NAMES
+                        .iter()
+                        .map(|(_, flag)| entry.flags.contains(*flag).to_string()),
+                );
+
+                if let Err(e) = self.writer.write_record(&csv_record) {
+                    println!("failed to serialize record to csv: {}", e);
+                    continue;
                 }
+                if let Err(e) = self.writer.flush() {
+                    println!("failed to write record to file: {}", e);
+                    break;
+                }
+                self.written_count += 1;
             }
 
             true
         }
+
+        fn export_diagnostics(&mut self, diagnostics: &[Diagnostic]) -> bool {
+            self.sidecars.export_diagnostics(diagnostics)
+        }
+
+        fn export_renames(&mut self, renames: &[Rename]) -> bool {
+            self.sidecars.export_renames(renames)
+        }
+
+        fn export_history(&mut self, histories: &[FileHistory]) -> bool {
+            self.sidecars.export_history(histories)
+        }
+
+        fn written_count(&self) -> usize {
+            self.written_count
+        }
     } // impl Registry for CsvRegistry
 } // mod csv
 
@@ -145,40 +610,103 @@ pub mod sqlite {
     use std::time::UNIX_EPOCH;
 
     use rusqlite;
+    use rusqlite::types::Value;
 
-    use crate::fsevents::Archive;
-    use crate::registry::Registry;
+    use crate::fsevents::{ArchiveInfo, Diagnostic, Entry, EventFlag};
+    use crate::history::FileHistory;
+    use crate::registry::{bound_secs, history_row, Registry};
+    use crate::renames::Rename;
 
+    /// Writes entries into the `record` table of a SQLite database, with a
+    /// boolean column per flag bit, parse errors into the `parse_errors` table,
+    /// renames into the `renames` table and file histories into the
+    /// `file_history` table.
     pub struct SqliteRegistry {
         pub written_count: usize,
 
         conn: rusqlite::Connection,
+        insert_record: String, // insert statement including the flag columns
     }
 
     impl SqliteRegistry {
         pub fn new(path: &str) -> Result<Box<SqliteRegistry>, rusqlite::Error> {
+            let flag_names = EventFlag::NAMES.map(|(name, _)| name);
             let conn = rusqlite::Connection::open(path)?;
             conn.execute(
-                "CREATE TABLE record (
+                &format!(
+                    "CREATE TABLE record (
                 path TEXT, 
                 id TEXT NOT NULL, 
                 flags TEXT, 
+                flags_raw INTEGER,
+                event_types TEXT,
+                node_id INTEGER,
+                extra_v3 INTEGER,
+                recovered INTEGER,
                 create_ts INTEGER,
                 modify_ts INTEGER,
+                source TEXT NOT NULL,
+                source_offset INTEGER,
+                volume_uuid TEXT,
+                time_lower_bound INTEGER,
+                time_upper_bound INTEGER,
+                {}
+            )",
+                    flag_names
+                        .map(|name| format!("{} INTEGER", name))
+                        .join(",\n")
+                ),
+                (),
+            )?;
+            conn.execute(
+                "CREATE TABLE parse_errors (
+                source TEXT NOT NULL,
+                page INTEGER,
+                offset INTEGER,
+                kind TEXT NOT NULL,
+                message TEXT,
+                bytes_skipped INTEGER
+            )",
+                (),
+            )?;
+            conn.execute(
+                "CREATE TABLE renames (
+                old_path TEXT,
+                new_path TEXT,
+                event_id TEXT NOT NULL,
+                node_id INTEGER,
                 source TEXT NOT NULL
             )",
                 (),
             )?;
 
+            let columns = 15 + flag_names.len();
+            let insert_record = format!(
+                "INSERT INTO record (
+                path, id, flags, flags_raw, event_types, node_id, extra_v3, recovered, create_ts, modify_ts,
+                source, source_offset, volume_uuid, time_lower_bound, time_upper_bound, {})
+                VALUES ({})",
+                flag_names.join(", "),
+                (1..=columns)
+                    .map(|i| format!("?{}", i))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+
             Ok(Box::new(SqliteRegistry {
                 written_count: 0,
                 conn,
+                insert_record,
             }))
         }
     } // impl SqliteRegistry
 
     impl Registry for SqliteRegistry {
-        fn export_archive(&mut self, archive: &Archive) -> bool {
+        fn export_entries(
+            &mut self,
+            archive: &ArchiveInfo,
+            entries: &mut dyn Iterator<Item = Entry>,
+        ) -> bool {
             // start transaction
             let txn = match self.conn.transaction() {
                 Ok(t) => t,
@@ -188,33 +716,46 @@ pub mod sqlite {
                 }
             };
 
-            for page in archive.pages.iter() {
-                for entry in page.entries.iter() {
-                    if let Err(e) = txn.execute(
-                        "INSERT INTO record (
-                        path, id, flags, create_ts, modify_ts, source) 
-                        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-                        (
-                            &entry.full_path,
-                            &entry.event_id.to_string(),
-                            format!("{:?}", entry.flags),
-                            archive
-                                .ctime
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_secs(),
-                            archive
-                                .mtime
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_secs(),
-                            &archive.filename,
-                        ),
-                    ) {
-                        println!("failed to insert record: {}", e);
-                        continue;
-                    }
+            for entry in entries {
+                let mut values: Vec<Value> = vec![
+                    entry.full_path.clone().into(),
+                    entry.event_id.to_string().into(),
+                    format!("{:?}", entry.flags).into(),
+                    entry.flags_raw.into(),
+                    entry.event_types().join("; ").into(),
+                    entry.node_id.map(|n| n as i64).into(),
+                    entry.extra_v3.into(),
+                    entry.recovered.into(),
+                    (archive
+                        .ctime
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs() as i64)
+                        .into(),
+                    (archive
+                        .mtime
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs() as i64)
+                        .into(),
+                    archive.filename.clone().into(),
+                    archive.source_offset.map(|o| o as i64).into(),
+                    archive.volume_uuid.clone().into(),
+                    bound_secs(entry.time_lower_bound).map(|t| t as i64).into(),
+                    bound_secs(entry.time_upper_bound).map(|t| t as i64).into(),
+                ];
+                values.extend(
+                    EventFlag::NAMES
+                        .iter()
+                        .map(|(_, flag)| entry.flags.contains(*flag).into()),
+                );
+
+                if let Err(e) = txn.execute(&self.insert_record, rusqlite::params_from_iter(values))
+                {
+                    println!("failed to insert record: {}", e);
+                    continue;
                 }
+                self.written_count += 1;
             }
 
             // end transaction
@@ -225,5 +766,357 @@ pub mod sqlite {
 
             true
         }
+
+        fn export_diagnostics(&mut self, diagnostics: &[Diagnostic]) -> bool {
+            let txn = match self.conn.transaction() {
+                Ok(t) => t,
+                Err(e) => {
+                    println!("failed to create transaction: {}", e);
+                    return false;
+                }
+            };
+            for diagnostic in diagnostics {
+                if let Err(e) = txn.execute(
+                    "INSERT INTO parse_errors (
+                    source, page, offset, kind, message, bytes_skipped)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    (
+                        &diagnostic.archive,
+                        diagnostic.page,
+                        diagnostic.offset as i64,
+                        diagnostic.kind,
+                        &diagnostic.message,
+                        diagnostic.bytes_skipped as i64,
+                    ),
+                ) {
+                    println!("failed to insert parse error: {}", e);
+                    continue;
+                }
+            }
+            if let Err(e) = txn.commit() {
+                println!("failed to commit transaction: {}", e);
+                return false;
+            }
+
+            true
+        }
+
+        fn export_renames(&mut self, renames: &[Rename]) -> bool {
+            let txn = match self.conn.transaction() {
+                Ok(t) => t,
+                Err(e) => {
+                    println!("failed to create transaction: {}", e);
+                    return false;
+                }
+            };
+            for rename in renames {
+                if let Err(e) = txn.execute(
+                    "INSERT INTO renames (
+                    old_path, new_path, event_id, node_id, source)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    (
+                        &rename.old_path,
+                        &rename.new_path,
+                        &rename.event_id.to_string(),
+                        rename.node_id.map(|n| n as i64),
+                        &rename.source,
+                    ),
+                ) {
+                    println!("failed to insert rename: {}", e);
+                    continue;
+                }
+            }
+            if let Err(e) = txn.commit() {
+                println!("failed to commit transaction: {}", e);
+                return false;
+            }
+
+            true
+        }
+
+        fn export_history(&mut self, histories: &[FileHistory]) -> bool {
+            if let Err(e) = self.conn.execute(
+                "CREATE TABLE IF NOT EXISTS file_history (
+                node_id INTEGER,
+                path TEXT,
+                paths TEXT,
+                first_seen TEXT NOT NULL,
+                last_seen TEXT NOT NULL,
+                event_count INTEGER,
+                created TEXT,
+                modified_count INTEGER,
+                last_modified TEXT,
+                renamed_to TEXT,
+                deleted TEXT,
+                deleted_at_end INTEGER
+            )",
+                (),
+            ) {
+                println!("failed to create file_history table: {}", e);
+                return false;
+            }
+
+            let txn = match self.conn.transaction() {
+                Ok(t) => t,
+                Err(e) => {
+                    println!("failed to create transaction: {}", e);
+                    return false;
+                }
+            };
+            for history in histories {
+                let row = history_row(history);
+                if let Err(e) = txn.execute(
+                    "INSERT INTO file_history (
+                    node_id, path, paths, first_seen, last_seen, event_count, created,
+                    modified_count, last_modified, renamed_to, deleted, deleted_at_end)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    (
+                        history.node_id.map(|n| n as i64),
+                        &row[1],
+                        &row[2],
+                        &row[3],
+                        &row[4],
+                        history.event_count as i64,
+                        &row[6],
+                        history.modified_count as i64,
+                        history.last_modified.map(|id| id.to_string()),
+                        &row[9],
+                        &row[10],
+                        history.deleted_at_end(),
+                    ),
+                ) {
+                    println!("failed to insert file history: {}", e);
+                    continue;
+                }
+            }
+            if let Err(e) = txn.commit() {
+                println!("failed to commit transaction: {}", e);
+                return false;
+            }
+
+            true
+        }
+
+        fn written_count(&self) -> usize {
+            self.written_count
+        }
     } // impl Registry for SqliteRegistry
 } // mod sqlite
+
+pub mod fseventsparser {
+
+    use std::fs;
+
+    use csv;
+
+    use crate::fsevents::{ArchiveInfo, Diagnostic, Entry};
+    use crate::history::FileHistory;
+    use crate::registry::{delimited_writer, DelimitedSidecars, Registry};
+    use crate::renames::Rename;
+    use crate::timeline::format_time;
+
+    const RECORD_HEADER: [&str; 12] = [
+        "id",
+        "id_hex",
+        "fullpath",
+        "filename",
+        "type",
+        "flags",
+        "approx_dates(plus_minus_one_day)",
+        "mask",
+        "node_id",
+        "record_end_offset",
+        "source",
+        "source_modified_time",
+    ];
+
+    /// Writes tab separated rows in the column layout of the Python
+    /// FSEventsParser, parse errors to a `<output>.parse_errors.tsv` sidecar,
+    /// renames to a `<output>.renames.tsv` sidecar and file histories to a
+    /// `<output>.file_history.tsv` sidecar.
+    pub struct FseventsParserRegistry {
+        pub written_count: usize,
+
+        writer: csv::Writer<fs::File>,
+        sidecars: DelimitedSidecars,
+    }
+
+    impl FseventsParserRegistry {
+        pub fn new(path: &str) -> Result<Box<FseventsParserRegistry>, std::io::Error> {
+            let mut writer = delimited_writer(path, b'\t')?;
+            writer.write_record(RECORD_HEADER)?;
+
+            Ok(Box::new(FseventsParserRegistry {
+                written_count: 0,
+                writer,
+                sidecars: DelimitedSidecars::new(path, b'\t', "tsv")?,
+            }))
+        }
+    } // impl FseventsParserRegistry
+
+    impl Registry for FseventsParserRegistry {
+        fn export_entries(
+            &mut self,
+            archive: &ArchiveInfo,
+            entries: &mut dyn Iterator<Item = Entry>,
+        ) -> bool {
+            let source_modified_time = format_time(archive.mtime);
+            for entry in entries {
+                let summary = entry.summary();
+                let tsv_record = [
+                    entry.event_id.to_string(),
+                    summary.id_hex,
+                    entry.full_path,
+                    summary.filename,
+                    summary.item_type,
+                    summary.flags_hex,
+                    match (entry.time_lower_bound, entry.time_upper_bound) {
+                        (None, None) => String::new(),
+                        (lower, upper) => format!(
+                            "{} - {}",
+                            lower.map(format_time).unwrap_or_default(),
+                            upper.map(format_time).unwrap_or_default()
+                        ),
+                    },
+                    summary.mask,
+                    entry.node_id.map(|n| n.to_string()).unwrap_or_default(),
+                    entry.end_offset.to_string(),
+                    archive.filename.clone(),
+                    source_modified_time.clone(),
+                ];
+
+                if let Err(e) = self.writer.write_record(&tsv_record) {
+                    println!("failed to serialize record to tsv: {}", e);
+                    continue;
+                }
+                if let Err(e) = self.writer.flush() {
+                    println!("failed to write record to file: {}", e);
+                    break;
+                }
+                self.written_count += 1;
+            }
+
+            true
+        }
+
+        fn export_diagnostics(&mut self, diagnostics: &[Diagnostic]) -> bool {
+            self.sidecars.export_diagnostics(diagnostics)
+        }
+
+        fn export_renames(&mut self, renames: &[Rename]) -> bool {
+            self.sidecars.export_renames(renames)
+        }
+
+        fn export_history(&mut self, histories: &[FileHistory]) -> bool {
+            self.sidecars.export_history(histories)
+        }
+
+        fn written_count(&self) -> usize {
+            self.written_count
+        }
+    } // impl Registry for FseventsParserRegistry
+} // mod fseventsparser
+
+pub mod sorted {
+
+    use crate::fsevents::{ArchiveInfo, Diagnostic, Entry};
+    use crate::history::FileHistory;
+    use crate::registry::Registry;
+    use crate::renames::Rename;
+    use crate::sort::ExternalSorter;
+
+    /// Holds entries back and writes them to the wrapped registry sorted by
+    /// event id across all archives when finished. Runs of consecutive
+    /// entries from the same archive are exported together.
+    pub struct SortedRegistry {
+        inner: Box<dyn Registry>,
+        archives: Vec<ArchiveInfo>,
+        sorter: Option<ExternalSorter>,
+    }
+
+    impl SortedRegistry {
+        pub fn new(inner: Box<dyn Registry>, run_size: usize) -> Box<SortedRegistry> {
+            Box::new(SortedRegistry {
+                inner,
+                archives: vec![],
+                sorter: Some(ExternalSorter::new(run_size)),
+            })
+        }
+    } // impl SortedRegistry
+
+    impl Registry for SortedRegistry {
+        fn export_entries(
+            &mut self,
+            archive: &ArchiveInfo,
+            entries: &mut dyn Iterator<Item = Entry>,
+        ) -> bool {
+            let sorter = match self.sorter.as_mut() {
+                Some(sorter) => sorter,
+                None => return false, // already finished
+            };
+
+            let index = self.archives.len();
+            self.archives.push(archive.clone());
+            for entry in entries {
+                if let Err(e) = sorter.push(index, entry) {
+                    println!("failed to spill sorted run: {}", e);
+                    return false;
+                }
+            }
+
+            true
+        }
+
+        fn export_diagnostics(&mut self, diagnostics: &[Diagnostic]) -> bool {
+            self.inner.export_diagnostics(diagnostics)
+        }
+
+        fn export_renames(&mut self, renames: &[Rename]) -> bool {
+            self.inner.export_renames(renames)
+        }
+
+        fn export_history(&mut self, histories: &[FileHistory]) -> bool {
+            self.inner.export_history(histories)
+        }
+
+        fn written_count(&self) -> usize {
+            self.inner.written_count()
+        }
+
+        fn finish(&mut self) -> bool {
+            let sorted: Box<dyn Iterator<Item = Result<(usize, Entry), std::io::Error>>> =
+                match self.sorter.take().map(|s| s.finish()) {
+                    Some(Ok(sorted)) => Box::new(sorted),
+                    // keep what was parsed, sorted within each run only
+                    Some(Err((e, sorter))) => {
+                        println!("failed to merge sorted runs, writing them unmerged: {}", e);
+                        Box::new(sorter.unmerged())
+                    }
+                    None => return false,
+                };
+
+            let mut sorted = sorted
+                .filter_map(|r| match r {
+                    Ok(tagged) => Some(tagged),
+                    Err(e) => {
+                        println!("failed to read sorted run: {}", e);
+                        None
+                    }
+                })
+                .peekable();
+            while let Some((index, _)) = sorted.peek() {
+                let index = *index;
+                let mut run = std::iter::from_fn(|| {
+                    sorted.next_if(|(i, _)| *i == index).map(|(_, entry)| entry)
+                });
+                if !self.inner.export_entries(&self.archives[index], &mut run) {
+                    return false;
+                }
+                // drain what the registry left unread
+                run.for_each(drop);
+            }
+
+            self.inner.finish()
+        }
+    } // impl Registry for SortedRegistry
+} // mod sorted
@@ -0,0 +1,147 @@
+//! Reconstruction of renames from pairs of rename records.
+//!
+//! fseventsd logs a rename as two `FSE_RENAME` records, the old path then the
+//! new path, with adjacent event ids and, from 2SLD on, the same node id.
+//! A rename is kept when either of its records passed the entry filter, so
+//! files moved into or out of the filtered paths are not lost.
+
+use std::collections::BTreeMap;
+
+use crate::fsevents::{Entry, EventFlag};
+
+// rename records waiting for their other half
+const MAX_PENDING: usize = 4096;
+
+/// A file or folder moved from `old_path` to `new_path`.
+#[derive(Debug, Clone)]
+pub struct Rename {
+    pub old_path: String,
+    pub new_path: String,
+    pub event_id: u64,     // event id of the old path record
+    pub new_event_id: u64, // event id of the new path record
+    pub node_id: Option<u64>,
+    pub source: String, // archive the old path record was read from
+}
+
+// one half of a rename
+#[derive(Debug)]
+struct Half {
+    path: String,
+    node_id: Option<u64>,
+    source: String,
+    passed: bool, // the record passed the entry filter
+}
+
+/// Pairs rename records as they are seen, in any order.
+#[derive(Debug, Default)]
+pub struct RenameTracker {
+    pending: BTreeMap<u64, Half>, // unpaired rename records by event id
+    renames: Vec<Rename>,         // pairs not taken yet
+}
+
+impl RenameTracker {
+    pub fn new() -> Self {
+        RenameTracker::default()
+    }
+
+    /// Looks at an entry read from `archive`, pairing it if it completes a
+    /// rename; `passed` tells whether the entry passed the entry filter.
+    pub fn observe(&mut self, entry: &Entry, archive: &str, passed: bool) {
+        if !entry.flags.contains(EventFlag::FSE_RENAME) {
+            return;
+        }
+
+        let id = entry.event_id;
+        let matches = |half: &Half| match (half.node_id, entry.node_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        // the old path record precedes the new path record
+        let before = id
+            .checked_sub(1)
+            .filter(|i| self.pending.get(i).is_some_and(matches));
+        let after = id
+            .checked_add(1)
+            .filter(|i| self.pending.get(i).is_some_and(matches));
+
+        let half = Half {
+            path: entry.full_path.clone(),
+            node_id: entry.node_id,
+            source: String::from(archive),
+            passed,
+        };
+        let (old_id, new_id, old, new) = match (before, after) {
+            (Some(i), _) => (i, id, self.pending.remove(&i).unwrap(), half),
+            (None, Some(i)) => (id, i, half, self.pending.remove(&i).unwrap()),
+            (None, None) => {
+                self.pending.insert(id, half);
+                if self.pending.len() > MAX_PENDING {
+                    self.pending.pop_first();
+                }
+                return;
+            }
+        };
+
+        if !old.passed && !new.passed {
+            return;
+        }
+        self.renames.push(Rename {
+            old_path: old.path,
+            new_path: new.path,
+            event_id: old_id,
+            new_event_id: new_id,
+            node_id: old.node_id.or(new.node_id),
+            source: old.source,
+        });
+    }
+
+    /// Renames paired since the last call.
+    pub fn take(&mut self) -> Vec<Rename> {
+        std::mem::take(&mut self.renames)
+    }
+
+    /// Rename records left without a partner.
+    pub fn unpaired_count(&self) -> usize {
+        self.pending.len()
+    }
+} // impl RenameTracker
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(path: &str, event_id: u64) -> Entry {
+        let flags = EventFlag::FSE_RENAME | EventFlag::FSE_IS_FILE;
+        Entry {
+            node_id: Some(event_id / 10),
+            ..Entry::for_test(path, event_id, flags)
+        }
+    }
+
+    #[test]
+    fn kept_when_either_side_passed() {
+        let mut renames = RenameTracker::new();
+        // moved out of the filtered paths, new path record first
+        renames.observe(&rename("tmp/x.txt", 11), "a", false);
+        renames.observe(&rename("Users/a/x.txt", 10), "a", true);
+        // moved into them
+        renames.observe(&rename("tmp/y.txt", 20), "a", false);
+        renames.observe(&rename("Users/a/y.txt", 21), "a", true);
+        // neither side passed
+        renames.observe(&rename("tmp/z.txt", 30), "a", false);
+        renames.observe(&rename("tmp/w.txt", 31), "a", false);
+
+        let paired = renames.take();
+        assert_eq!(paired.len(), 2);
+        assert_eq!(
+            (paired[0].old_path.as_str(), paired[0].new_path.as_str()),
+            ("Users/a/x.txt", "tmp/x.txt")
+        );
+        assert_eq!((paired[0].event_id, paired[0].new_event_id), (10, 11));
+        assert_eq!(
+            (paired[1].old_path.as_str(), paired[1].new_path.as_str()),
+            ("tmp/y.txt", "Users/a/y.txt")
+        );
+        assert_eq!(renames.unpaired_count(), 0);
+    }
+} // mod tests
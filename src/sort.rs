@@ -0,0 +1,562 @@
+//! External merge sort of entries by event id.
+//!
+//! Entries are buffered up to a run size, sorted and spilled to a temporary
+//! file; the runs are then merged, at most `MAX_FAN_IN` at a time, in as many
+//! passes as needed. Only one run and one record per merged run are in memory
+//! at a time, whatever the size of the input.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::fsevents::{Entry, EventFlag, Version};
+
+/// Entries held in memory before a run is spilled to disk.
+pub const DEFAULT_RUN_SIZE: usize = 1 << 20;
+
+/// Runs merged at once, each an open file; more runs are first merged into
+/// longer ones.
+pub const MAX_FAN_IN: usize = 64;
+
+/// Sorts entries tagged with the index of their archive by event id. Entries
+/// with the same event id keep the order they were pushed in.
+#[derive(Debug)]
+pub struct ExternalSorter {
+    run_size: usize,
+    fan_in: usize,
+    buffer: Vec<(usize, Entry)>,
+    dir: Option<PathBuf>, // created with the first spilled run
+    runs: Vec<PathBuf>,   // in push order
+    run_count: usize,     // run files created so far, to name the next one
+}
+
+impl ExternalSorter {
+    pub fn new(run_size: usize) -> Self {
+        ExternalSorter {
+            run_size: run_size.max(1),
+            fan_in: MAX_FAN_IN,
+            buffer: vec![],
+            dir: None,
+            runs: vec![],
+            run_count: 0,
+        }
+    }
+
+    /// Merges at most `fan_in` runs at once instead of `MAX_FAN_IN`.
+    pub fn with_fan_in(mut self, fan_in: usize) -> Self {
+        self.fan_in = fan_in.max(2);
+        self
+    }
+
+    /// Adds an entry of the archive `archive`, spilling a run when the
+    /// buffer is full.
+    pub fn push(&mut self, archive: usize, entry: Entry) -> Result<(), std::io::Error> {
+        self.buffer.push((archive, entry));
+        if self.buffer.len() >= self.run_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // path of a new run file
+    fn next_run(&mut self) -> Result<PathBuf, std::io::Error> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let nanos = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos();
+                let dir = std::env::temp_dir().join(format!(
+                    "fsevents_parser_rs-{}-{}",
+                    std::process::id(),
+                    nanos
+                ));
+                fs::create_dir_all(&dir)?;
+                self.dir = Some(dir.clone());
+                dir
+            }
+        };
+        self.run_count += 1;
+        Ok(dir.join(format!("run{}", self.run_count)))
+    }
+
+    // write the buffer sorted to a new run file
+    fn spill(&mut self) -> Result<(), std::io::Error> {
+        let path = self.next_run()?;
+        self.buffer.sort_by_key(|(_, e)| e.event_id);
+        let mut writer = BufWriter::new(fs::File::create(&path)?);
+        for (archive, entry) in self.buffer.drain(..) {
+            write_record(&mut writer, archive, &entry)?;
+        }
+        writer.flush()?;
+        self.runs.push(path);
+
+        Ok(())
+    }
+
+    // merge consecutive runs until at most `fan_in` are left; on failure the
+    // runs left still hold every entry, in push order
+    fn merge_down(&mut self) -> Result<(), std::io::Error> {
+        while self.runs.len() > self.fan_in {
+            let mut merged = vec![];
+            for start in (0..self.runs.len()).step_by(self.fan_in) {
+                let group = self.runs[start..(start + self.fan_in).min(self.runs.len())].to_vec();
+                let written = self
+                    .next_run()
+                    .and_then(|path| merge_into(&group, &path).map(|_| path));
+                match written {
+                    Ok(path) => {
+                        group.iter().for_each(|run| _ = fs::remove_file(run));
+                        merged.push(path);
+                    }
+                    Err(e) => {
+                        merged.extend(self.runs.drain(start..));
+                        self.runs = merged;
+                        return Err(e);
+                    }
+                }
+            }
+            self.runs = merged;
+        }
+        Ok(())
+    }
+
+    /// Merges everything pushed into one stream sorted by event id. On
+    /// failure the sorter is handed back with every entry still in it, see
+    /// [`ExternalSorter::unmerged`].
+    pub fn finish(mut self) -> Result<SortedEntries, (std::io::Error, ExternalSorter)> {
+        // everything fit in memory
+        if self.runs.is_empty() {
+            self.buffer.sort_by_key(|(_, e)| e.event_id);
+            let buffer = std::mem::take(&mut self.buffer);
+            return Ok(SortedEntries {
+                memory: buffer.into_iter(),
+                merger: None,
+                _sorter: self,
+            });
+        }
+
+        let merged = match self.buffer.is_empty() {
+            true => Ok(()),
+            false => self.spill(),
+        }
+        .and_then(|_| self.merge_down())
+        .and_then(|_| Merger::open(&self.runs));
+        match merged {
+            Ok(merger) => Ok(SortedEntries {
+                memory: vec![].into_iter(),
+                merger: Some(merger),
+                _sorter: self,
+            }),
+            Err(e) => Err((e, self)),
+        }
+    }
+
+    /// Everything pushed, run by run in push order, each run sorted by event
+    /// id, for when the runs cannot be merged. Only one run file is open at
+    /// a time.
+    pub fn unmerged(mut self) -> UnmergedEntries {
+        self.buffer.sort_by_key(|(_, e)| e.event_id);
+        UnmergedEntries {
+            memory: std::mem::take(&mut self.buffer).into_iter(),
+            runs: self.runs.clone().into_iter(),
+            current: None,
+            _sorter: self,
+        }
+    }
+} // impl ExternalSorter
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+// merge the runs at `paths` into a new run file at `path`
+fn merge_into(paths: &[PathBuf], path: &Path) -> Result<(), std::io::Error> {
+    let result = Merger::open(paths).and_then(|merger| {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        for record in merger {
+            let (archive, entry) = record?;
+            write_record(&mut writer, archive, &entry)?;
+        }
+        writer.flush()
+    });
+    if result.is_err() {
+        _ = fs::remove_file(path);
+    }
+    result
+}
+
+// k-way merge of sorted runs; equal event ids come out in run order
+struct Merger {
+    runs: Vec<BufReader<fs::File>>,
+    heap: BinaryHeap<Reverse<(u64, usize)>>, // next event id of each run
+    heads: Vec<Option<(usize, Entry)>>,
+}
+
+impl Merger {
+    fn open(paths: &[PathBuf]) -> Result<Self, std::io::Error> {
+        let mut merger = Merger {
+            runs: vec![],
+            heap: BinaryHeap::new(),
+            heads: vec![],
+        };
+        for (i, path) in paths.iter().enumerate() {
+            let mut reader = BufReader::new(fs::File::open(path)?);
+            let head = read_record(&mut reader)?;
+            if let Some((_, entry)) = &head {
+                merger.heap.push(Reverse((entry.event_id, i)));
+            }
+            merger.runs.push(reader);
+            merger.heads.push(head);
+        }
+        Ok(merger)
+    }
+}
+
+impl Iterator for Merger {
+    type Item = Result<(usize, Entry), std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, run)) = self.heap.pop()?;
+        let next = match read_record(&mut self.runs[run]) {
+            Ok(next) => next,
+            Err(e) => {
+                self.heap.clear();
+                return Some(Err(e));
+            }
+        };
+        if let Some((_, entry)) = &next {
+            self.heap.push(Reverse((entry.event_id, run)));
+        }
+        std::mem::replace(&mut self.heads[run], next).map(Ok)
+    }
+}
+
+/// Entries merged from the runs of an [`ExternalSorter`], with the index of
+/// their archive. The run files are removed once it is dropped.
+pub struct SortedEntries {
+    memory: std::vec::IntoIter<(usize, Entry)>,
+    merger: Option<Merger>,
+    _sorter: ExternalSorter, // owns the run files
+}
+
+impl Iterator for SortedEntries {
+    type Item = Result<(usize, Entry), std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.merger.as_mut() {
+            Some(merger) => merger.next(),
+            None => self.memory.next().map(Ok),
+        }
+    }
+}
+
+/// Entries of an [`ExternalSorter`] run by run, see
+/// [`ExternalSorter::unmerged`]. A run that cannot be read yields an error
+/// and the next run is read.
+pub struct UnmergedEntries {
+    memory: std::vec::IntoIter<(usize, Entry)>,
+    runs: std::vec::IntoIter<PathBuf>,
+    current: Option<BufReader<fs::File>>,
+    _sorter: ExternalSorter, // owns the run files
+}
+
+impl Iterator for UnmergedEntries {
+    type Item = Result<(usize, Entry), std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(reader) = self.current.as_mut() {
+                match read_record(reader) {
+                    Ok(Some(record)) => return Some(Ok(record)),
+                    Ok(None) => self.current = None,
+                    Err(e) => {
+                        self.current = None;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            match self.runs.next() {
+                Some(path) => match fs::File::open(path) {
+                    Ok(fd) => self.current = Some(BufReader::new(fd)),
+                    Err(e) => return Some(Err(e)),
+                },
+                None => return self.memory.next().map(Ok),
+            }
+        }
+    }
+}
+
+/*
+ * run file record, little endian
+ * | archive index | 4 bytes
+ * | path length | 4 bytes, then the path
+ * | event id | 8 bytes
+ * | flags | 4 bytes
+ * | raw flags | 4 bytes
+ * | version | 1 byte
+ * | recovered | 1 byte
+ * | end offset | 8 bytes
+ * | node id, extra v3, time bounds | presence byte, then the value
+ */
+
+fn write_record(w: &mut impl Write, archive: usize, entry: &Entry) -> std::io::Result<()> {
+    w.write_all(&(archive as u32).to_le_bytes())?;
+    w.write_all(&(entry.full_path.len() as u32).to_le_bytes())?;
+    w.write_all(entry.full_path.as_bytes())?;
+    w.write_all(&entry.event_id.to_le_bytes())?;
+    w.write_all(&entry.flags.bits().to_le_bytes())?;
+    w.write_all(&entry.flags_raw.to_le_bytes())?;
+    w.write_all(&[version_byte(entry.version), entry.recovered as u8])?;
+    w.write_all(&entry.end_offset.to_le_bytes())?;
+
+    match entry.node_id {
+        Some(node_id) => {
+            w.write_all(&[1])?;
+            w.write_all(&node_id.to_le_bytes())?;
+        }
+        None => w.write_all(&[0])?,
+    }
+    match entry.extra_v3 {
+        Some(extra) => {
+            w.write_all(&[1])?;
+            w.write_all(&extra.to_le_bytes())?;
+        }
+        None => w.write_all(&[0])?,
+    }
+    for bound in [entry.time_lower_bound, entry.time_upper_bound] {
+        match bound {
+            Some(time) => {
+                let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                w.write_all(&[1])?;
+                w.write_all(&since.as_secs().to_le_bytes())?;
+                w.write_all(&since.subsec_nanos().to_le_bytes())?;
+            }
+            None => w.write_all(&[0])?,
+        }
+    }
+
+    Ok(())
+}
+
+// next record of a run, None at its end
+fn read_record(r: &mut impl Read) -> std::io::Result<Option<(usize, Entry)>> {
+    let mut archive = [0u8; 4];
+    match r.read_exact(&mut archive) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut path = vec![0u8; read_u32(r)? as usize];
+    r.read_exact(&mut path)?;
+    let event_id = read_u64(r)?;
+    let flags = read_u32(r)?;
+    let flags_raw = read_u32(r)?;
+    let version = match read_u8(r)? {
+        1 => Version::V1,
+        2 => Version::V2,
+        3 => Version::V3,
+        _ => Version::Unknown,
+    };
+    let recovered = read_u8(r)? != 0;
+    let end_offset = read_u64(r)?;
+    let node_id = match read_u8(r)? {
+        0 => None,
+        _ => Some(read_u64(r)?),
+    };
+    let extra_v3 = match read_u8(r)? {
+        0 => None,
+        _ => Some(read_u32(r)?),
+    };
+    let mut bounds = [None, None];
+    for bound in bounds.iter_mut() {
+        if read_u8(r)? != 0 {
+            let secs = read_u64(r)?;
+            let nanos = read_u32(r)?;
+            *bound = Some(UNIX_EPOCH + Duration::new(secs, nanos));
+        }
+    }
+
+    Ok(Some((
+        u32::from_le_bytes(archive) as usize,
+        Entry {
+            full_path: String::from_utf8_lossy(&path).into_owned(),
+            event_id,
+            flags: EventFlag::from_bits_truncate(flags),
+            flags_raw,
+            node_id,
+            extra_v3,
+            recovered,
+            version,
+            end_offset,
+            time_lower_bound: bounds[0],
+            time_upper_bound: bounds[1],
+        },
+    )))
+}
+
+fn version_byte(version: Version) -> u8 {
+    match version {
+        Version::Unknown => 0,
+        Version::V1 => 1,
+        Version::V2 => 2,
+        Version::V3 => 3,
+    }
+}
+
+fn read_u8(r: &mut impl Read) -> std::io::Result<u8> {
+    let mut bytes = [0u8; 1];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(r: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, event_id: u64) -> Entry {
+        Entry::for_test(
+            path,
+            event_id,
+            EventFlag::FSE_CONTENT_MODIFIED | EventFlag::FSE_IS_FILE,
+        )
+    }
+
+    // sort ids pushed in order, returning (archive, path) in merged order
+    fn sorted(run_size: usize, fan_in: usize, ids: &[u64]) -> (usize, Vec<(usize, String)>) {
+        let mut sorter = ExternalSorter::new(run_size).with_fan_in(fan_in);
+        for (i, id) in ids.iter().enumerate() {
+            sorter.push(i % 3, entry(&format!("p{}", i), *id)).unwrap();
+        }
+        let runs = sorter.runs.len();
+        let merged = sorter
+            .finish()
+            .unwrap()
+            .map(|r| r.map(|(archive, e)| (archive, e.full_path)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        (runs, merged)
+    }
+
+    // (archive, path) of `ids` pushed in order, sorted by event id
+    fn expected(ids: &[u64]) -> Vec<(usize, String)> {
+        let mut expected = ids.iter().enumerate().collect::<Vec<_>>();
+        expected.sort_by_key(|(i, id)| (**id, *i));
+        expected
+            .into_iter()
+            .map(|(i, _)| (i % 3, format!("p{}", i)))
+            .collect()
+    }
+
+    #[test]
+    fn merges_runs_in_event_id_then_push_order() {
+        let ids = [5, 1, 5, 3, 1, 5, 2, 5, 4, 1, 3];
+
+        // four runs, the last one spilled by finish
+        let (runs, merged) = sorted(3, MAX_FAN_IN, &ids);
+        assert_eq!(runs, 3);
+        assert_eq!(merged, expected(&ids));
+
+        // everything in memory
+        let (runs, merged) = sorted(100, MAX_FAN_IN, &ids);
+        assert_eq!(runs, 0);
+        assert_eq!(merged, expected(&ids));
+    }
+
+    #[test]
+    fn merges_in_passes_with_bounded_fan_in() {
+        let ids = (0..200u64).map(|i| i * 37 % 23).collect::<Vec<_>>();
+
+        // 100 runs merged 3 at a time, down to 34, 12, 4 and 2 runs
+        let (runs, merged) = sorted(2, 3, &ids);
+        assert_eq!(runs, 100);
+        assert_eq!(merged, expected(&ids));
+
+        let mut sorter = ExternalSorter::new(2).with_fan_in(3);
+        for (i, id) in ids.iter().enumerate() {
+            sorter.push(i % 3, entry(&format!("p{}", i), *id)).unwrap();
+        }
+        sorter.merge_down().unwrap();
+        assert!(sorter.runs.len() <= 3);
+        let dir = sorter.dir.clone().unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), sorter.runs.len());
+        drop(sorter);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn unmerged_keeps_every_entry() {
+        let ids = [5, 1, 5, 3, 1, 5, 2, 5, 4, 1, 3];
+        let mut sorter = ExternalSorter::new(4);
+        for (i, id) in ids.iter().enumerate() {
+            sorter.push(i % 3, entry(&format!("p{}", i), *id)).unwrap();
+        }
+
+        // two runs of four, then the three entries left in memory
+        let unmerged = sorter
+            .unmerged()
+            .map(|r| r.map(|(_, e)| e.event_id))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(unmerged, vec![1, 3, 5, 5, 1, 2, 5, 5, 1, 3, 4]);
+    }
+
+    #[test]
+    fn run_record_round_trip() {
+        let mut full = entry("Users/a/caf\u{e9} \u{1f600}.txt", u64::MAX - 1);
+        full.flags = EventFlag::FSE_RENAME | EventFlag::FSE_IS_DIR;
+        full.flags_raw = full.flags.bits() | 0x8000_0000;
+        full.node_id = Some(0x0102_0304_0506_0708);
+        full.extra_v3 = Some(0xdead_beef);
+        full.recovered = true;
+        full.version = Version::V3;
+        full.end_offset = 1 << 40;
+        full.time_lower_bound = Some(UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789));
+        full.time_upper_bound = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_060));
+        let mut partial = entry("", 0);
+        partial.version = Version::Unknown;
+        partial.time_upper_bound = Some(UNIX_EPOCH);
+
+        let mut run = vec![];
+        write_record(&mut run, 7, &full).unwrap();
+        write_record(&mut run, 0, &partial).unwrap();
+        let mut reader = run.as_slice();
+        for (archive, written) in [(7, &full), (0, &partial)] {
+            let (read_archive, read) = read_record(&mut reader).unwrap().unwrap();
+            assert_eq!(read_archive, archive);
+            assert_eq!(read.full_path, written.full_path);
+            assert_eq!(read.event_id, written.event_id);
+            assert_eq!(read.flags, written.flags);
+            assert_eq!(read.flags_raw, written.flags_raw);
+            assert_eq!(read.node_id, written.node_id);
+            assert_eq!(read.extra_v3, written.extra_v3);
+            assert_eq!(read.recovered, written.recovered);
+            assert_eq!(read.version, written.version);
+            assert_eq!(read.end_offset, written.end_offset);
+            assert_eq!(read.time_lower_bound, written.time_lower_bound);
+            assert_eq!(read.time_upper_bound, written.time_upper_bound);
+        }
+        assert!(read_record(&mut reader).unwrap().is_none());
+    }
+} // mod tests
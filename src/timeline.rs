@@ -0,0 +1,528 @@
+//! Estimation of wall-clock times for event ids.
+//!
+//! Event ids only grow, but records carry no time. An archive is written when
+//! its last event is flushed, so its mtime tells that every event up to its
+//! last id happened before that time, and every later event after it. Those
+//! archive anchors, plus exact anchors supplied by the user (e.g. the creation
+//! event of a file with a known crtime), bound the time of every event id.
+//!
+//! When fseventsd is reset, event ids start over. Anchors are split into
+//! epochs at each reset by their time, and an event is estimated from the
+//! anchors of the epoch its archive was written in.
+
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::fsevents::{name_id_ranges, Archive, ArchiveInfo, Entry};
+use crate::gaps::GapTracker;
+
+/// An event id tied to a wall-clock time.
+#[derive(Debug, Clone)]
+pub struct Anchor {
+    pub event_id: u64,
+    pub time: SystemTime,
+    /// The event happened at `time`; otherwise it happened at or before it,
+    /// as for the last event of an archive.
+    pub exact: bool,
+    pub source: String, // what the anchor was taken from
+}
+
+/// Interval an event happened in; a side is `None` when no anchor bounds it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub lower: Option<SystemTime>,
+    pub upper: Option<SystemTime>,
+}
+
+/// Event ids read from an archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveSpan {
+    pub filename: String,
+    pub mtime: SystemTime,
+    pub first: u64,
+    pub last: u64,
+}
+
+/// Bounds event times from anchors kept in event id order, per epoch.
+#[derive(Debug, Clone)]
+pub struct TimeEstimator {
+    resets: Vec<SystemTime>,  // times event ids started over after, ascending
+    epochs: Vec<Vec<Anchor>>, // anchors between two resets, in event id order
+}
+
+impl Default for TimeEstimator {
+    fn default() -> Self {
+        TimeEstimator::new()
+    }
+}
+
+impl TimeEstimator {
+    pub fn new() -> Self {
+        TimeEstimator {
+            resets: vec![],
+            epochs: vec![vec![]],
+        }
+    }
+
+    /// Anchors the last event id of every archive at `paths`, sorted by name,
+    /// to its mtime, with epochs split where the archive names go back in
+    /// mtime order. Archives for which `read` is true are read once, their
+    /// entries handed to `observe`, and anchored by their highest event id;
+    /// the others by the event id in their name. Returns the event ids read
+    /// from each archive.
+    pub fn from_archives<R, F>(
+        paths: &[String],
+        recover: bool,
+        read: R,
+        mut observe: F,
+    ) -> (Self, Vec<ArchiveSpan>)
+    where
+        R: Fn(&str) -> bool,
+        F: FnMut(&Entry),
+    {
+        let mut estimator = TimeEstimator::new();
+        let (paths, infos): (Vec<&String>, Vec<ArchiveInfo>) = paths
+            .iter()
+            .filter_map(|p| Some((p, ArchiveInfo::from_path(p).ok()?)))
+            .unzip();
+
+        let mut rollbacks = GapTracker::new();
+        for (info, range) in infos.iter().zip(name_id_ranges(&infos)) {
+            if let Some((first, last)) = range {
+                rollbacks.observe(&info.filename, info.mtime, first, last);
+            }
+        }
+        rollbacks
+            .resets()
+            .into_iter()
+            .for_each(|t| estimator.add_reset(t));
+
+        let mut spans = vec![];
+        for (path, info) in paths.into_iter().zip(infos) {
+            if !read(path) {
+                if let Some(last) = info.last_event_id_hint {
+                    estimator.add_archive(&info, last);
+                }
+                continue;
+            }
+            let fd = match fs::File::open(path) {
+                Ok(fd) => fd,
+                Err(_) => continue, // reported when exporting
+            };
+            let range = Archive::entries(fd)
+                .with_recovery(recover)
+                .filter_map(|r| r.ok())
+                .inspect(&mut observe)
+                .fold(None, |range: Option<(u64, u64)>, e| match range {
+                    Some((first, last)) => Some((first.min(e.event_id), last.max(e.event_id))),
+                    None => Some((e.event_id, e.event_id)),
+                });
+            if let Some((first, last)) = range {
+                estimator.add_archive(&info, last);
+                spans.push(ArchiveSpan {
+                    filename: info.filename,
+                    mtime: info.mtime,
+                    first,
+                    last,
+                });
+            }
+        }
+
+        (estimator, spans)
+    }
+
+    /// Adds an anchor to the epoch of its time, keeping the anchors ordered
+    /// by event id.
+    pub fn add_anchor(&mut self, anchor: Anchor) {
+        let anchors = &mut self.epochs[epoch_of(&self.resets, anchor.time)];
+        let at = anchors.partition_point(|a| a.event_id <= anchor.event_id);
+        anchors.insert(at, anchor);
+    }
+
+    /// Anchors the last event id of an archive to its mtime.
+    pub fn add_archive(&mut self, info: &ArchiveInfo, last_event_id: u64) {
+        self.add_anchor(Anchor {
+            event_id: last_event_id,
+            time: info.mtime,
+            exact: false,
+            source: info.filename.clone(),
+        });
+    }
+
+    /// Starts a new epoch after `time`, the mtime of the last archive written
+    /// before event ids went back; anchors are moved to the epochs of their
+    /// times.
+    pub fn add_reset(&mut self, time: SystemTime) {
+        if let Err(at) = self.resets.binary_search(&time) {
+            self.resets.insert(at, time);
+            let anchors = self.epochs.drain(..).flatten().collect::<Vec<Anchor>>();
+            self.epochs = vec![vec![]; self.resets.len() + 1];
+            anchors.into_iter().for_each(|a| self.add_anchor(a));
+        }
+    }
+
+    /// Adds `anchors` that agree with the anchors already known, in event
+    /// id order, and returns the ones rejected. An anchor agrees when its time
+    /// lies in the window estimated for its event id, e.g. a file copied with
+    /// its original crtime is rejected.
+    pub fn fit_anchors(&mut self, mut anchors: Vec<Anchor>) -> Vec<Anchor> {
+        anchors.sort_by_key(|a| a.event_id);
+
+        let mut rejected = vec![];
+        for anchor in anchors {
+            let window = self.estimate(anchor.event_id, anchor.time);
+            let fits = window.lower.is_none_or(|t| t <= anchor.time)
+                && window.upper.is_none_or(|t| anchor.time <= t);
+            match fits {
+                true => self.add_anchor(anchor),
+                false => rejected.push(anchor),
+            }
+        }
+        rejected
+    }
+
+    /// Anchors of every epoch, oldest epoch first.
+    pub fn anchors(&self) -> impl Iterator<Item = &Anchor> {
+        self.epochs.iter().flatten()
+    }
+
+    /// Anchors of the epoch of an archive written at `written`.
+    pub fn epoch_anchors(&self, written: SystemTime) -> &[Anchor] {
+        &self.epochs[epoch_of(&self.resets, written)]
+    }
+
+    /// Time window of `event_id` in an archive written at `written`, bounded
+    /// by the nearest anchors around it in the epoch of that archive and by
+    /// the resets around the epoch. Anchors that contradict each other leave
+    /// the window open.
+    pub fn estimate(&self, event_id: u64, written: SystemTime) -> TimeWindow {
+        let epoch = epoch_of(&self.resets, written);
+        let anchors = &self.epochs[epoch];
+
+        // an event follows every anchor below its id, and an exact anchor on it
+        let lower = anchors[..anchors.partition_point(|a| a.event_id <= event_id)]
+            .iter()
+            .rev()
+            .find(|a| a.event_id < event_id || a.exact)
+            .map(|a| a.time);
+        let upper = anchors[anchors.partition_point(|a| a.event_id < event_id)..]
+            .first()
+            .map(|a| a.time);
+
+        // events of an epoch happened between the resets around it
+        let started = epoch.checked_sub(1).map(|i| self.resets[i]);
+        let ended = self.resets.get(epoch).copied();
+        let window = TimeWindow {
+            lower: lower.max(started),
+            upper: match (upper, ended) {
+                (Some(upper), Some(ended)) => Some(upper.min(ended)),
+                (upper, ended) => upper.or(ended),
+            },
+        };
+        match (window.lower, window.upper) {
+            (Some(lower), Some(upper)) if lower > upper => TimeWindow::default(),
+            _ => window,
+        }
+    }
+
+    /// Widest time window of the event ids from `first` to `last` in an
+    /// archive written at `written`, `None` when a side of some window is
+    /// unbounded.
+    pub fn uncertainty(&self, first: u64, last: u64, written: SystemTime) -> Option<Duration> {
+        // windows only change right after an anchor
+        let mut ids = vec![first];
+        for anchor in &self.epochs[epoch_of(&self.resets, written)] {
+            for id in [anchor.event_id, anchor.event_id.saturating_add(1)] {
+                if first < id && id <= last {
+                    ids.push(id);
+                }
+            }
+        }
+
+        ids.into_iter()
+            .map(|id| {
+                let window = self.estimate(id, written);
+                let width = window.upper?.duration_since(window.lower?);
+                Some(width.unwrap_or_default())
+            })
+            .collect::<Option<Vec<Duration>>>()?
+            .into_iter()
+            .max()
+    }
+
+    /// Sets the time bounds of `entry`, read from an archive written at
+    /// `written`, from its event id.
+    pub fn apply(&self, entry: &mut Entry, written: SystemTime) {
+        let window = self.estimate(entry.event_id, written);
+        entry.time_lower_bound = window.lower;
+        entry.time_upper_bound = window.upper;
+    }
+} // impl TimeEstimator
+
+// index of the epoch a time falls in, a reset time closing its epoch
+fn epoch_of(resets: &[SystemTime], time: SystemTime) -> usize {
+    resets.partition_point(|r| *r < time)
+}
+
+/// Parses a time given as unix seconds or as UTC `YYYY-MM-DD[ HH:MM:SS]`,
+/// with `T` allowed as separator and an optional trailing `Z`.
+pub fn parse_time(s: &str) -> Option<SystemTime> {
+    if let Ok(secs) = s.parse::<u64>() {
+        return Some(UNIX_EPOCH + Duration::from_secs(secs));
+    }
+
+    let s = s.trim_end_matches('Z');
+    let (date, time) = match s.split_once([' ', 'T']) {
+        Some((date, time)) => (date, time),
+        None => (s, "00:00:00"),
+    };
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    let time: Vec<i64> = time
+        .split(':')
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    if date.len() != 3 || time.len() != 3 {
+        return None;
+    }
+    let (year, month, day) = (date[0], date[1], date[2]);
+    let (hour, minute, second) = (time[0], time[1], time[2]);
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..60).contains(&second)
+    {
+        return None;
+    }
+
+    // days since epoch from civil date, proleptic gregorian calendar
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Formats a time as UTC `YYYY-MM-DD HH:MM:SS`.
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // civil date from days since epoch, proleptic gregorian calendar
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn anchor(event_id: u64, secs: u64, exact: bool) -> Anchor {
+        Anchor {
+            event_id,
+            time: at(secs),
+            exact,
+            source: String::from("test"),
+        }
+    }
+
+    fn window(lower: Option<u64>, upper: Option<u64>) -> TimeWindow {
+        TimeWindow {
+            lower: lower.map(at),
+            upper: upper.map(at),
+        }
+    }
+
+    #[test]
+    fn estimate_between_anchors() {
+        let mut estimator = TimeEstimator::new();
+        estimator.add_anchor(anchor(100, 1000, false));
+        estimator.add_anchor(anchor(200, 2000, false));
+        estimator.add_anchor(anchor(150, 1500, true));
+
+        let written = at(2000);
+        assert_eq!(estimator.estimate(50, written), window(None, Some(1000)));
+        // the last event of an archive happened at or before its mtime
+        assert_eq!(estimator.estimate(100, written), window(None, Some(1000)));
+        assert_eq!(
+            estimator.estimate(101, written),
+            window(Some(1000), Some(1500))
+        );
+        // an exact anchor bounds its own event on both sides
+        assert_eq!(
+            estimator.estimate(150, written),
+            window(Some(1500), Some(1500))
+        );
+        assert_eq!(estimator.estimate(201, written), window(Some(2000), None));
+    }
+
+    #[test]
+    fn estimate_across_a_reset() {
+        // the old archive 0x800 was written at 1000, the archive 0x40 after
+        // the reset at 3000
+        let mut estimator = TimeEstimator::new();
+        estimator.add_anchor(anchor(0x800, 1000, false));
+        estimator.add_anchor(anchor(0x40, 3000, false));
+        estimator.add_reset(at(1000));
+
+        assert_eq!(
+            estimator.estimate(0x700, at(1000)),
+            window(None, Some(1000))
+        );
+        assert_eq!(
+            estimator.estimate(0x800, at(1000)),
+            window(None, Some(1000))
+        );
+        assert_eq!(
+            estimator.estimate(0x30, at(3000)),
+            window(Some(1000), Some(3000))
+        );
+        // ids past the last archive of an old epoch end at the reset
+        assert_eq!(
+            estimator.estimate(0x900, at(1000)),
+            window(Some(1000), Some(1000))
+        );
+        assert_eq!(estimator.epoch_anchors(at(3000)).len(), 1);
+        assert_eq!(estimator.anchors().count(), 2);
+    }
+
+    #[test]
+    fn contradicting_anchors_leave_the_window_open() {
+        let mut estimator = TimeEstimator::new();
+        estimator.add_anchor(anchor(100, 2000, false));
+        estimator.add_anchor(anchor(200, 1000, false));
+        assert_eq!(estimator.estimate(150, at(1000)), TimeWindow::default());
+
+        let windows = (0..300).map(|id| estimator.estimate(id, at(1000)));
+        for w in windows {
+            if let (Some(lower), Some(upper)) = (w.lower, w.upper) {
+                assert!(lower <= upper);
+            }
+        }
+    }
+
+    #[test]
+    fn uncertainty_is_the_widest_window() {
+        let mut estimator = TimeEstimator::new();
+        estimator.add_anchor(anchor(100, 1000, false));
+        estimator.add_anchor(anchor(200, 1600, false));
+        estimator.add_anchor(anchor(300, 1700, false));
+
+        let written = at(1700);
+        assert_eq!(
+            estimator.uncertainty(101, 300, written),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(
+            estimator.uncertainty(201, 300, written),
+            Some(Duration::from_secs(100))
+        );
+        // the first ids are unbounded below
+        assert_eq!(estimator.uncertainty(50, 300, written), None);
+        assert_eq!(estimator.uncertainty(250, 350, written), None);
+    }
+
+    #[test]
+    fn fit_anchors_rejects_inconsistent_ones() {
+        let mut estimator = TimeEstimator::new();
+        estimator.add_anchor(anchor(100, 1000, false));
+        estimator.add_anchor(anchor(200, 2000, false));
+
+        let rejected = estimator.fit_anchors(vec![
+            // before the anchor at 140, which is fitted first
+            anchor(150, 1500, true),
+            // a copied file with an older crtime
+            anchor(180, 500, true),
+            anchor(140, 1800, true),
+        ]);
+        let rejected = rejected.iter().map(|a| a.event_id).collect::<Vec<_>>();
+        assert_eq!(rejected, vec![150, 180]);
+        assert_eq!(estimator.anchors().count(), 3);
+        assert_eq!(
+            estimator.estimate(160, at(2000)),
+            window(Some(1800), Some(2000))
+        );
+    }
+
+    #[test]
+    fn parse_time_round_trip() {
+        for s in [
+            "1970-01-01 00:00:00",
+            "2024-02-29 23:59:59",
+            "2000-02-29 12:00:00",
+            "2023-12-31 00:00:59",
+        ] {
+            assert_eq!(format_time(parse_time(s).unwrap()), s);
+        }
+        assert_eq!(
+            parse_time("2024-01-02T03:04:05Z"),
+            parse_time("2024-01-02 03:04:05")
+        );
+        assert_eq!(parse_time("2024-01-02"), parse_time("2024-01-02 00:00:00"));
+        assert_eq!(parse_time("86400"), parse_time("1970-01-02"));
+    }
+
+    #[test]
+    fn parse_time_rejects_invalid_values() {
+        for s in [
+            "2024-01-01 25:99:99",
+            "2024-01-01 24:00:00",
+            "2024-01-01 12:60:00",
+            "2024-01-01 12:00:60",
+            "2024-01-01 -1:00:00",
+            "2024-02-31",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-04-31",
+            "2024-13-01",
+            "2024-00-10",
+            "2024-01-00",
+            "1969-12-31",
+            "2024-01-01 12:00",
+            "yesterday",
+        ] {
+            assert_eq!(parse_time(s), None, "{}", s);
+        }
+    }
+} // mod tests
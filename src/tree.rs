@@ -0,0 +1,247 @@
+//! Directory tree of every path seen across the archives.
+//!
+//! Each node is marked from its creation and deletion events: it existed at
+//! the end of the log, was deleted, or was transient, i.e. created and
+//! deleted inside the window the log covers. A path a file was renamed to
+//! counts as created by the rename, and the old path is marked renamed.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::fsevents::{Entry, EventFlag};
+use crate::renames::Rename;
+
+/// Existence of a path at the end of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Existed,   // not deleted, or created again after its last deletion
+    Deleted,   // deleted, created before the log began
+    Transient, // created, or renamed to, and deleted inside the log
+    Renamed,   // moved to another path
+}
+
+impl NodeState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeState::Existed => "existed",
+            NodeState::Deleted => "deleted",
+            NodeState::Transient => "transient",
+            NodeState::Renamed => "renamed",
+        }
+    }
+}
+
+/// A path component; nodes only seen as parents of other paths have no
+/// events of their own.
+#[derive(Debug, Clone, Default)]
+pub struct TreeNode {
+    pub name: String,
+    pub is_dir: bool,
+    pub created: Option<u64>, // last creation event id
+    pub deleted: Option<u64>, // last deletion event id
+    pub renamed: Option<u64>, // last event id it was renamed away at
+    pub arrived: Option<u64>, // last event id a file was renamed to it at
+    pub event_count: usize,
+    pub children: BTreeMap<String, TreeNode>,
+}
+
+impl TreeNode {
+    pub fn state(&self) -> NodeState {
+        let created = self.created.max(self.arrived);
+        let gone = self.deleted.max(self.renamed);
+        match (created, gone) {
+            (_, None) => NodeState::Existed,
+            (Some(created), Some(gone)) if created > gone => NodeState::Existed,
+            _ if self.renamed > self.deleted => NodeState::Renamed,
+            (Some(_), Some(_)) => NodeState::Transient,
+            (None, Some(_)) => NodeState::Deleted,
+        }
+    }
+
+    /// Nodes below this one that no longer exist.
+    pub fn gone_below(&self) -> usize {
+        self.children
+            .values()
+            .map(|c| c.gone_below() + usize::from(c.state() != NodeState::Existed))
+            .sum()
+    }
+} // impl TreeNode
+
+/// Tree of the paths of all entries observed, rooted at the volume root.
+#[derive(Debug, Clone)]
+pub struct PathTree {
+    pub root: TreeNode,
+}
+
+impl Default for PathTree {
+    fn default() -> Self {
+        PathTree {
+            root: TreeNode {
+                name: String::from("/"),
+                is_dir: true,
+                ..TreeNode::default()
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonNode<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    node_type: &'static str,
+    state: &'static str,
+    created: Option<u64>,
+    deleted: Option<u64>,
+    renamed: Option<u64>,
+    arrived: Option<u64>,
+    event_count: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<JsonNode<'a>>,
+}
+
+impl PathTree {
+    pub fn new() -> Self {
+        PathTree::default()
+    }
+
+    /// Adds the path of an entry, and its creation or deletion.
+    pub fn observe(&mut self, entry: &Entry) {
+        let node = self.node(&entry.full_path);
+        let id = entry.event_id;
+        let flags = entry.flags;
+        node.event_count += 1;
+        node.is_dir |= flags.contains(EventFlag::FSE_IS_DIR);
+        if flags.intersects(EventFlag::FSE_CREATE_FILE | EventFlag::FSE_CREATE_DIR) {
+            node.created = node.created.max(Some(id));
+        }
+        if flags.contains(EventFlag::FSE_DELETE) {
+            node.deleted = node.deleted.max(Some(id));
+        }
+    }
+
+    /// Marks the old path of a rename as renamed away, and the new path as
+    /// arrived at; both were added with the entries of their records.
+    pub fn add_rename(&mut self, rename: &Rename) {
+        let node = self.node(&rename.old_path);
+        node.renamed = node.renamed.max(Some(rename.event_id));
+        let node = self.node(&rename.new_path);
+        node.arrived = node.arrived.max(Some(rename.new_event_id));
+    }
+
+    // node of `path`, created with its parents when missing
+    fn node(&mut self, path: &str) -> &mut TreeNode {
+        let mut node = &mut self.root;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node.is_dir = true;
+            node = node
+                .children
+                .entry(String::from(component))
+                .or_insert_with(|| TreeNode {
+                    name: String::from(component),
+                    ..TreeNode::default()
+                });
+        }
+        node
+    }
+
+    /// Writes the tree indented by two spaces per level, a node per line.
+    pub fn write_text(&self, w: &mut impl Write) -> Result<(), std::io::Error> {
+        write_text_node(w, &self.root, 0)
+    }
+
+    /// Writes the tree as one nested JSON object.
+    pub fn write_json(&self, w: &mut impl Write) -> Result<(), std::io::Error> {
+        serde_json::to_writer_pretty(&mut *w, &json_node(&self.root))?;
+        w.write_all(b"\n")
+    }
+} // impl PathTree
+
+fn write_text_node(
+    w: &mut impl Write,
+    node: &TreeNode,
+    depth: usize,
+) -> Result<(), std::io::Error> {
+    let mut line = format!("{}{}", "  ".repeat(depth), node.name);
+    if node.is_dir && depth > 0 {
+        line.push('/');
+    }
+    line.push_str(&format!(" [{}]", node.state().name()));
+    let events = [
+        ("created", node.created),
+        ("deleted", node.deleted),
+        ("renamed", node.renamed),
+        ("arrived", node.arrived),
+    ]
+    .iter()
+    .filter_map(|(what, id)| Some(format!("{} {}", what, (*id)?)))
+    .collect::<Vec<String>>();
+    if !events.is_empty() {
+        line.push_str(&format!(" ({})", events.join(", ")));
+    }
+    let gone = node.gone_below();
+    if gone > 0 {
+        line.push_str(&format!(" {} gone below", gone));
+    }
+    writeln!(w, "{}", line)?;
+
+    for child in node.children.values() {
+        write_text_node(w, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+fn json_node(node: &TreeNode) -> JsonNode<'_> {
+    JsonNode {
+        name: &node.name,
+        node_type: match node.is_dir {
+            true => "dir",
+            false => "file",
+        },
+        state: node.state().name(),
+        created: node.created,
+        deleted: node.deleted,
+        renamed: node.renamed,
+        arrived: node.arrived,
+        event_count: node.event_count,
+        children: node.children.values().map(json_node).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(tree: &PathTree, path: &str) -> NodeState {
+        let mut node = &tree.root;
+        for component in path.split('/') {
+            node = &node.children[component];
+        }
+        node.state()
+    }
+
+    #[test]
+    fn renamed_in_then_deleted_is_transient() {
+        let renamed = EventFlag::FSE_RENAME | EventFlag::FSE_IS_FILE;
+        let mut tree = PathTree::new();
+        tree.observe(&Entry::for_test("Users/a/x.txt", 3, renamed));
+        tree.observe(&Entry::for_test("Users/a/y.txt", 4, renamed));
+        tree.observe(&Entry::for_test("Users/a/y.txt", 10, EventFlag::FSE_DELETE));
+        tree.observe(&Entry::for_test("Users/a/z.txt", 11, EventFlag::FSE_DELETE));
+        tree.add_rename(&Rename {
+            old_path: String::from("Users/a/x.txt"),
+            new_path: String::from("Users/a/y.txt"),
+            event_id: 3,
+            new_event_id: 4,
+            node_id: Some(7),
+            source: String::new(),
+        });
+
+        assert_eq!(state(&tree, "Users/a/x.txt"), NodeState::Renamed);
+        assert_eq!(state(&tree, "Users/a/y.txt"), NodeState::Transient);
+        assert_eq!(state(&tree, "Users/a/z.txt"), NodeState::Deleted);
+        assert_eq!(tree.root.gone_below(), 3);
+    }
+} // mod tests